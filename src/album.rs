@@ -0,0 +1,48 @@
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+/// Trailing suffixes of edition and disc, e.g. "(Deluxe Edition)", "(Remastered 2011)" or "[Disc 1]".
+/// Matching is case-insensitive
+static DEFAULT_SUFFIXES: LazyLock<Vec<Regex>> = LazyLock::new(|| {
+    [
+        r"(?i)\s*[(\[][^)\]]*\b(edition|version|deluxe|expanded|anniversary|remaster(ed)?|bonus tracks?)\b[^)\]]*[)\]]\s*$",
+        r"(?i)\s*[(\[]\s*(disc|disk|cd)\s*\d+\s*[)\]]\s*$",
+    ]
+    .iter()
+    .map(|pattern| { Regex::new(pattern).unwrap() })
+    .collect()
+});
+
+/// Length of the MusicBrainz ID part added to album directories, enough to tell editions apart
+const ALBUM_ID_PREFIX_LEN: usize = 8;
+
+/// Album followed by the beginning of its MusicBrainz release ID, e.g. "Album [0c8c9b3e]"
+pub fn with_album_id(album: &str, album_id: &str) -> String {
+    let prefix: String = album_id.chars().take(ALBUM_ID_PREFIX_LEN).collect();
+    format!("{} [{}]", album, prefix)
+}
+
+/// Album followed by bit depth and sample rate for `--audio-suffix`, e.g. "Album [24-96]"
+pub fn with_audio_suffix(album: &str, suffix: &str) -> String {
+    format!("{} [{}]", album, suffix)
+}
+
+/// Strips edition and disc suffixes, so that e.g. "Album (Deluxe Edition) [Disc 1]" becomes "Album".
+/// Parts matching `extra_patterns` are removed anywhere in the album.
+/// Album that would end up empty is kept as is
+pub fn clean_album(album: &str, extra_patterns: &[Regex]) -> String {
+    let mut result = album.to_string();
+    for pattern in extra_patterns {
+        result = pattern.replace_all(&result, "").into_owned();
+    }
+    // Suffixes may be chained, so they are stripped until none is left
+    while let Some(pattern) = DEFAULT_SUFFIXES.iter().find(|pattern| { pattern.is_match(&result) }) {
+        result = pattern.replace(&result, "").into_owned();
+    }
+
+    match result.trim() {
+        "" => album.to_string(),
+        cleaned => cleaned.to_string(),
+    }
+}
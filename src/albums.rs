@@ -0,0 +1,98 @@
+use std::{collections::{BTreeMap, BTreeSet}, path::PathBuf};
+
+use colored::Colorize;
+
+use crate::{duplicates::comparable, tags::{self, ReadOptions}};
+
+/// Disc of an album among passed files with fewer tracks than expected
+pub struct IncompleteAlbum {
+    /// Tags as written in the first file of the album
+    pub artist: String,
+    pub album: String,
+    /// Only set when the album has several discs
+    pub disc: Option<u32>,
+    pub found: usize,
+    pub expected: usize,
+    /// Track numbers below the total that no file has, empty when the total isn't tagged
+    pub missing: Vec<u32>,
+}
+
+#[derive(Default)]
+struct AlbumTracks {
+    artist: String,
+    album: String,
+    disc: Option<u32>,
+    is_multi_disc: bool,
+    numbers: BTreeSet<u32>,
+    /// Files without track number, each of them counts as a separate track
+    unnumbered: usize,
+    total_tracks: Option<u32>,
+}
+
+/// Groups files by their artist, album and disc, ignoring case and whitespace differences, and returns the discs
+/// with fewer tracks than the highest total tracks tag of their files, or than `min_track_count` when there is no such tag.
+/// Files with the same track number, e.g. in different formats, are counted once. Files with unreadable tags are counted separately
+pub fn find_incomplete_albums(files: &[PathBuf], options: &ReadOptions, min_track_count: usize) -> (Vec<IncompleteAlbum>, usize) {
+    let mut albums: BTreeMap<(String, String, Option<u32>), AlbumTracks> = BTreeMap::new();
+    let mut unreadable = 0;
+    for file in files {
+        let Ok(tags) = tags::read_tags(file, options) else {
+            unreadable += 1;
+            continue;
+        };
+        let key = (comparable(&tags.artist), comparable(&tags.album), tags.disc);
+        let album = albums.entry(key)
+            .or_insert_with(|| { AlbumTracks { artist: tags.artist.clone(), album: tags.album.clone(), disc: tags.disc, ..AlbumTracks::default() } });
+        match tags.track {
+            Some(track) => { album.numbers.insert(track); },
+            None => album.unnumbered += 1,
+        }
+        album.total_tracks = album.total_tracks.max(tags.total_tracks);
+        album.is_multi_disc |= tags.disc.is_some_and(|disc| { disc > 1 }) || tags.total_discs.is_some_and(|total| { total > 1 });
+    }
+
+    let incomplete = albums.into_values()
+        .filter_map(|album| {
+            let found = album.numbers.len() + album.unnumbered;
+            let expected = album.total_tracks.map(|total| { total as usize }).unwrap_or(min_track_count);
+            if found >= expected {
+                return None;
+            }
+            let missing = album.total_tracks
+                .map(|total| { (1..=total).filter(|track| { !album.numbers.contains(track) }).collect() })
+                .unwrap_or_default();
+            Some(IncompleteAlbum {
+                artist: album.artist,
+                album: album.album,
+                disc: album.disc.filter(|_| { album.is_multi_disc }),
+                found,
+                expected,
+                missing,
+            })
+        })
+        .collect();
+    (incomplete, unreadable)
+}
+
+pub fn print_incomplete_albums(albums: &[IncompleteAlbum], unreadable: usize) {
+    for album in albums {
+        let disc = album.disc.map(|disc| { format!(" (disc {})", disc) }).unwrap_or_default();
+        let missing = if album.missing.is_empty() {
+            String::new()
+        } else {
+            let numbers: Vec<String> = album.missing.iter().map(u32::to_string).collect();
+            format!(", missing {}", numbers.join(", "))
+        };
+        println!(
+            "{} {}",
+            format!("{} / {}{}", album.artist, album.album, disc).bold(),
+            format!("{} of {} tracks{}", album.found, album.expected, missing).yellow()
+        );
+    }
+
+    println!(
+        "{}, {}",
+        format!("{} incomplete albums", albums.len()).yellow().bold(),
+        format!("{} unreadable", unreadable).red().bold()
+    );
+}
@@ -0,0 +1,92 @@
+use clap::ValueEnum;
+
+/// Articles of `--ignore-articles` unless `--articles` is given
+pub const DEFAULT_ARTICLES: [&str; 3] = [ "The", "A", "An" ];
+/// Separators starting the list of featured artists, lowercase
+const FEATURING_SEPARATORS: [&str; 6] = [ " (feat.", " (ft.", " (featuring", " feat.", " ft.", " featuring" ];
+
+/// Cuts featured artists off, e.g. "Artist feat. Guest" becomes "Artist".
+/// Matching is case-insensitive
+pub fn strip_featured(artist: &str) -> &str {
+    // ASCII lowercasing keeps byte offsets valid for the original string
+    let lowercase = artist.to_ascii_lowercase();
+    let cut_at = FEATURING_SEPARATORS.iter()
+        .filter_map(|separator| { lowercase.find(separator) })
+        .min();
+
+    match cut_at {
+        Some(idx) => artist[..idx].trim_end(),
+        None => artist,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ArticleMode {
+    /// "The Beatles" becomes "Beatles"
+    Strip,
+    /// "The Beatles" becomes "Beatles, The"
+    Move,
+}
+
+/// Strips leading article or moves it to the end, e.g. "The Beatles" becomes "Beatles" or "Beatles, The".
+/// Matching is case-insensitive. When stripping, an article already moved to the end is stripped as well,
+/// so that "The Beatles" and "Beatles, The" end up in the same directory
+pub fn handle_article(artist: &str, articles: &[String], mode: ArticleMode) -> String {
+    let leading = articles.iter().find_map(|article| {
+        let prefix = artist.get(..article.len()).filter(|prefix| { prefix.eq_ignore_ascii_case(article) })?;
+        let rest = artist[article.len()..].strip_prefix(' ')?.trim_start();
+        // Artist named just "The" keeps its name
+        (!rest.is_empty()).then_some((prefix, rest))
+    });
+
+    match (leading, mode) {
+        (Some((_, rest)), ArticleMode::Strip) => rest.to_string(),
+        (Some((article, rest)), ArticleMode::Move) => format!("{}, {}", rest, article),
+        (None, ArticleMode::Strip) => {
+            let trailing = articles.iter().find_map(|article| {
+                let cut_at = artist.len().checked_sub(article.len() + 2)?;
+                let suffix = artist.get(cut_at..)?;
+                let is_article = suffix.starts_with(", ") && suffix[2..].eq_ignore_ascii_case(article);
+                is_article.then(|| { artist[..cut_at].trim_end() }).filter(|rest| { !rest.is_empty() })
+            });
+            trailing.unwrap_or(artist).to_string()
+        },
+        (None, ArticleMode::Move) => artist.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_feat() {
+        assert_eq!(strip_featured("Artist feat. Guest"), "Artist");
+        assert_eq!(strip_featured("Artist FEAT. Guest"), "Artist");
+    }
+
+    #[test]
+    fn strips_ft() {
+        assert_eq!(strip_featured("Artist ft. Guest"), "Artist");
+        assert_eq!(strip_featured("Artist Ft. Guest & Other"), "Artist");
+    }
+
+    #[test]
+    fn strips_featuring() {
+        assert_eq!(strip_featured("Artist featuring Guest"), "Artist");
+        assert_eq!(strip_featured("Artist Featuring Guest"), "Artist");
+    }
+
+    #[test]
+    fn strips_parenthesized_feat() {
+        assert_eq!(strip_featured("Artist (feat. Guest)"), "Artist");
+        assert_eq!(strip_featured("Artist (Ft. Guest)"), "Artist");
+    }
+
+    #[test]
+    fn keeps_names_containing_ft() {
+        assert_eq!(strip_featured("Daft Punk"), "Daft Punk");
+        assert_eq!(strip_featured("Left Feather"), "Left Feather");
+        assert_eq!(strip_featured("Soft Cell"), "Soft Cell");
+    }
+}
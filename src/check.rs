@@ -0,0 +1,106 @@
+use std::path::{Path, PathBuf};
+
+use colored::Colorize;
+
+use crate::{tags::{self, ReadOptions, Required}, ManageError};
+
+/// Results of `--check` over all files
+#[derive(Default)]
+pub struct CheckSummary {
+    pub complete: usize,
+    pub incomplete: usize,
+    pub unreadable: usize,
+    /// Amount of files missing each of `Required::ALL`
+    pub missing: [usize; 3],
+}
+
+enum FileCheck {
+    Missing(Vec<Required>),
+    Unreadable(String),
+}
+
+/// Reads tags of every file and reports which of artist, album and title are present, without computing targets.
+/// Files are incomplete only when they miss tags of `options.required`
+pub fn check(files: &[PathBuf], options: &ReadOptions) -> CheckSummary {
+    // All tags are read, so that missing ones are reported even when they are not required
+    let all_tags = ReadOptions { required: Required::ALL.to_vec(), ..options.clone() };
+    let longest_filename_len = files.iter()
+        .map(|file| { filename(file).chars().count() })
+        .max()
+        .unwrap_or(1);
+
+    let mut summary = CheckSummary::default();
+    for file in files {
+        let name = filename(file);
+        let dots = ".".repeat(longest_filename_len - name.chars().count() + 10);
+        match check_file(file, &all_tags) {
+            FileCheck::Missing(missing) => {
+                let presence: Vec<String> = Required::ALL.iter()
+                    .map(|tag| {
+                        if missing.contains(tag) && options.required.contains(tag) {
+                            format!("{} missing", tag.name()).red().bold().to_string()
+                        } else if missing.contains(tag) {
+                            format!("{} missing", tag.name()).yellow().to_string()
+                        } else {
+                            format!("{} ok", tag.name()).green().to_string()
+                        }
+                    })
+                    .collect();
+                println!("{}{}{}", name, dots, presence.join(", "));
+
+                for (idx, tag) in Required::ALL.iter().enumerate() {
+                    if missing.contains(tag) {
+                        summary.missing[idx] += 1;
+                    }
+                }
+                if !missing.iter().any(|tag| { options.required.contains(tag) }) {
+                    summary.complete += 1;
+                } else {
+                    summary.incomplete += 1;
+                }
+            },
+            FileCheck::Unreadable(err) => {
+                summary.unreadable += 1;
+                println!("{}{}{}", name, dots, err.red());
+            },
+        }
+    }
+
+    summary
+}
+
+pub fn print_check_summary(summary: &CheckSummary) {
+    // Most common problem first, so that it's clear what to fix first
+    let mut missing: Vec<(Required, usize)> = Required::ALL.into_iter().zip(summary.missing).collect();
+    missing.sort_by_key(|(_, count)| { std::cmp::Reverse(*count) });
+    let missing: Vec<String> = missing.iter()
+        .map(|(tag, count)| { format!("{} {}", count, tag.name()) })
+        .collect();
+
+    println!("Missing: {}", missing.join(", "));
+    println!(
+        "{}, {}, {}",
+        format!("{} complete", summary.complete).green().bold(),
+        format!("{} incomplete", summary.incomplete).yellow().bold(),
+        format!("{} unreadable", summary.unreadable).red().bold()
+    );
+}
+
+fn check_file(file: &Path, options: &ReadOptions) -> FileCheck {
+    let errors = match tags::read_tags(file, options) {
+        Ok(_) => return FileCheck::Missing(Vec::new()),
+        Err(errors) => errors,
+    };
+
+    let missing: Option<Vec<Required>> = errors.iter().map(Required::missing_in).collect();
+    match (missing, errors.first()) {
+        (Some(missing), _) => FileCheck::Missing(missing),
+        // File without any tags misses them all
+        (None, Some(ManageError::NoTags)) => FileCheck::Missing(Required::ALL.to_vec()),
+        (None, _) => FileCheck::Unreadable(errors.iter().map(crate::format_error).collect::<Vec<_>>().join(", ")),
+    }
+}
+
+fn filename(path: &Path) -> &str {
+    path.file_name().and_then(|fname| { fname.to_str() }).unwrap_or("<N/A>")
+}
@@ -0,0 +1,18 @@
+use std::{fs::File, io, path::Path};
+
+use sha2::{Digest, Sha256};
+
+/// Hex-encoded SHA-256 of file content
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+
+    Ok(hasher.finalize().iter().map(|byte| { format!("{:02x}", byte) }).collect())
+}
+
+/// First `length` hex digits of SHA-256 of `text`, used to keep shortened names unique
+pub fn short_hash(text: &str, length: usize) -> String {
+    let digest = Sha256::digest(text.as_bytes());
+    digest.iter().map(|byte| { format!("{:02x}", byte) }).collect::<String>()[..length].to_string()
+}
@@ -0,0 +1,116 @@
+use std::{fs, path::{Path, PathBuf}, process::exit};
+
+use clap::{builder::Resettable, Arg, ArgAction, Command, CommandFactory, FromArgMatches};
+use colored::Colorize;
+
+use crate::CliArgs;
+
+const CONFIG_FILE_NAME: &str = "config.toml";
+
+/// Parses command line arguments, using values from the config file as defaults.
+///
+/// Keys of the config file are the long names of command line flags, with `_` instead of `-`,
+/// e.g. `target_directory = "/music"` or `on_conflict = "rename"`. Options used multiple times take arrays,
+/// or tables of `NAME=VALUE` ones like `--artist-root`
+pub fn parse_args() -> CliArgs {
+    // First pass only looks for `--config`, so everything else is allowed to be wrong yet
+    let config_path = CliArgs::command()
+        .ignore_errors(true)
+        .get_matches()
+        .get_one::<PathBuf>("config")
+        .cloned();
+
+    let mut command = CliArgs::command();
+    let config_file = match config_path {
+        Some(path) => Some(path),
+        None => default_config_path().filter(|path| { path.exists() }),
+    };
+    if let Some(config_file) = config_file {
+        command = apply_config_file(command, &config_file).unwrap_or_else(|err| {
+            eprintln!("{} Cannot use config file '{}': {}", "ERROR!".red().bold(), config_file.display(), err);
+            exit(2);
+        });
+    }
+
+    let matches = command.get_matches();
+    CliArgs::from_arg_matches(&matches).unwrap_or_else(|err| { err.exit() })
+}
+
+/// `<platform config dir>/music-shelf-manager/config.toml`
+fn default_config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| { dir.join(env!("CARGO_PKG_NAME")).join(CONFIG_FILE_NAME) })
+}
+
+fn apply_config_file(mut command: Command, path: &Path) -> Result<Command, String> {
+    let content = fs::read_to_string(path).map_err(|err| { err.to_string() })?;
+    let table: toml::Table = content.parse().map_err(|err: toml::de::Error| { err.to_string() })?;
+
+    for (key, value) in table {
+        let long_name = key.replace('_', "-");
+        let arg = command.get_arguments()
+            .find(|arg| { arg.get_long() == Some(long_name.as_str()) && is_configurable(arg.get_action()) })
+            .ok_or_else(|| { format!("Unknown option '{}'", key) })?;
+        let id = arg.get_id().clone();
+        let accepts_many = matches!(arg.get_action(), ArgAction::Append);
+
+        let values = match value {
+            toml::Value::Array(values) if accepts_many => values.into_iter()
+                .map(|value| { to_arg_value(&key, value) })
+                .collect::<Result<Vec<_>, _>>()?,
+            // Entries keep the order of the file, e.g. for `[artist_root]` where the first match wins
+            toml::Value::Table(entries) if accepts_many => entries.into_iter()
+                .map(|(name, value)| { to_arg_value(&key, value).map(|value| { format!("{}={}", name, value) }) })
+                .collect::<Result<Vec<_>, _>>()?,
+            value => vec![to_arg_value(&key, value)?],
+        };
+
+        validate_values(arg, &values).map_err(|err| { format!("Invalid value of option '{}': {}", key, err) })?;
+
+        // Defaults never satisfy `required` or `required_unless_present`, so config value would be rejected otherwise
+        command = command.mut_arg(id, |arg| { arg.required(false).required_unless_present(Resettable::Reset).default_values(values) });
+    }
+
+    Ok(command)
+}
+
+/// Runs values through the same parser as command line would, so that config errors are reported up front
+fn validate_values(arg: &Arg, values: &[String]) -> Result<(), String> {
+    if matches!(arg.get_action(), ArgAction::SetTrue | ArgAction::SetFalse) {
+        return values.iter()
+            .try_for_each(|value| { value.parse::<bool>().map(|_| {}).map_err(|_| { format!("expected boolean, got '{}'", value) }) });
+    }
+
+    let flag = format!("--{}", arg.get_long().unwrap_or_default());
+    let cli_values: Vec<String> = values.iter().map(|value| { format!("{}={}", flag, value) }).collect();
+    // Only what parsing needs is copied, relations like `required_unless_present` would refer to arguments missing here
+    let parsing_arg = Arg::new(arg.get_id().clone())
+        .long(arg.get_long().unwrap_or_default().to_string())
+        .value_names(arg.get_value_names().unwrap_or_default().to_vec())
+        .action(arg.get_action().clone())
+        .value_parser(arg.get_value_parser().clone())
+        .value_delimiter(arg.get_value_delimiter());
+
+    Command::new("config")
+        .no_binary_name(true)
+        .arg(parsing_arg)
+        .try_get_matches_from(cli_values)
+        .map(|_| {})
+        .map_err(|err| {
+            let rendered = err.render().to_string();
+            rendered.lines().next().unwrap_or_default().trim_start_matches("error: ").to_string()
+        })
+}
+
+fn is_configurable(action: &ArgAction) -> bool {
+    matches!(action, ArgAction::Set | ArgAction::Append | ArgAction::SetTrue | ArgAction::SetFalse)
+}
+
+fn to_arg_value(key: &str, value: toml::Value) -> Result<String, String> {
+    match value {
+        toml::Value::String(value) => Ok(value),
+        toml::Value::Integer(value) => Ok(value.to_string()),
+        toml::Value::Float(value) => Ok(value.to_string()),
+        toml::Value::Boolean(value) => Ok(value.to_string()),
+        _ => Err(format!("Unsupported value type of option '{}'", key)),
+    }
+}
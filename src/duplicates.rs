@@ -0,0 +1,57 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use colored::Colorize;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::tags::{self, ReadOptions};
+
+/// Files with the same artist, album and title, likely the same song in different quality
+pub struct DuplicateGroup {
+    /// Tags as written in the first file of the group
+    pub artist: String,
+    pub album: String,
+    pub title: String,
+    pub files: Vec<PathBuf>,
+}
+
+/// Groups files by their artist, album and title, ignoring case and whitespace differences.
+/// Only groups of several files are returned, files with unreadable tags are counted separately
+pub fn find_duplicates(files: &[PathBuf], options: &ReadOptions) -> (Vec<DuplicateGroup>, usize) {
+    let mut groups: BTreeMap<(String, String, String), DuplicateGroup> = BTreeMap::new();
+    let mut unreadable = 0;
+    for file in files {
+        let Ok(tags) = tags::read_tags(file, options) else {
+            unreadable += 1;
+            continue;
+        };
+        let key = (comparable(&tags.artist), comparable(&tags.album), comparable(&tags.title));
+        groups.entry(key)
+            .or_insert_with(|| { DuplicateGroup { artist: tags.artist, album: tags.album, title: tags.title, files: Vec::new() } })
+            .files
+            .push(file.clone());
+    }
+
+    let duplicates = groups.into_values().filter(|group| { group.files.len() > 1 }).collect();
+    (duplicates, unreadable)
+}
+
+pub fn print_duplicates(groups: &[DuplicateGroup], unreadable: usize) {
+    for group in groups {
+        println!("{}", format!("{} / {} / {}", group.artist, group.album, group.title).bold());
+        for file in &group.files {
+            println!("    [{}] {}", tags::format_label(file), file.display());
+        }
+    }
+
+    let files: usize = groups.iter().map(|group| { group.files.len() }).sum();
+    println!(
+        "{}, {}",
+        format!("{} groups of duplicates ({} files)", groups.len(), files).yellow().bold(),
+        format!("{} unreadable", unreadable).red().bold()
+    );
+}
+
+/// Case, Unicode form and spacing differences don't make tags different
+pub(crate) fn comparable(value: &str) -> String {
+    value.nfc().collect::<String>().to_lowercase().split_whitespace().collect::<Vec<_>>().join(" ")
+}
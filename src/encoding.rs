@@ -0,0 +1,19 @@
+/// Checks tag value for signs of wrong decoding, e.g. Latin-1 bytes read as UTF-8 or vice versa.
+/// It's a heuristic, so both false positives and false negatives are possible
+pub fn looks_garbled(value: &str) -> bool {
+    if value.chars().any(|c| { c == char::REPLACEMENT_CHARACTER || is_c1_control(c) }) {
+        return true;
+    }
+
+    // UTF-8 read as Latin-1 turns e.g. "é" into "Ã©": lead byte followed by continuation byte
+    let chars: Vec<char> = value.chars().collect();
+    chars.windows(2).any(|pair| {
+        let (lead, continuation) = (pair[0], pair[1]);
+        matches!(lead, '\u{c2}'..='\u{c3}' | '\u{e2}') && matches!(continuation, '\u{80}'..='\u{bf}' | '\u{20ac}' | '\u{2122}' | '\u{201a}'..='\u{201e}')
+    })
+}
+
+/// C1 control characters are never typed deliberately, but appear when Windows-1252 text is decoded as Latin-1
+fn is_c1_control(c: char) -> bool {
+    matches!(c, '\u{80}'..='\u{9f}')
+}
@@ -0,0 +1,60 @@
+use std::{fmt, io};
+
+/// Reason why a file wasn't organized
+#[derive(Debug)]
+pub enum ManageError {
+    /// Tag needed for the target path is absent. `field` names it, e.g. "album" or "album artist (TPE2)"
+    MissingTag { field: String },
+    /// File has no tags at all
+    NoTags,
+    /// Tags are there, but can't be parsed
+    ReadFailed { message: String },
+    /// Filesystem operation failed, `context` tells which one
+    IoError { context: String, source: io::Error },
+    /// Target path can't be built with the current options
+    InvalidTarget { message: String },
+    /// Operation is not available for the format of the file
+    Unsupported { message: String },
+}
+
+impl ManageError {
+    pub fn missing_tag(field: impl Into<String>) -> ManageError {
+        ManageError::MissingTag { field: field.into() }
+    }
+
+    pub fn io(context: impl Into<String>, source: io::Error) -> ManageError {
+        ManageError::IoError { context: context.into(), source }
+    }
+}
+
+impl fmt::Display for ManageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ManageError::MissingTag { field } => write!(f, "Missing tag: {}", field),
+            ManageError::NoTags => write!(f, "No tags found"),
+            ManageError::ReadFailed { message } => write!(f, "Cannot read tags: {}", message),
+            ManageError::IoError { context, source } => write!(f, "{}: {}", context, source),
+            ManageError::InvalidTarget { message } | ManageError::Unsupported { message } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for ManageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ManageError::IoError { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<id3::Error> for ManageError {
+    fn from(err: id3::Error) -> Self {
+        match err.kind {
+            id3::ErrorKind::Io(source) => ManageError::io("Cannot read file", source),
+            id3::ErrorKind::NoTag => ManageError::NoTags,
+            kind if err.description.is_empty() => ManageError::ReadFailed { message: kind.to_string() },
+            _ => ManageError::ReadFailed { message: err.description },
+        }
+    }
+}
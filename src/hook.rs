@@ -0,0 +1,110 @@
+use std::{path::Path, process::{Child, Command, Stdio}, thread, time::{Duration, Instant}};
+
+/// Replaced with the album directory or the file in hook commands
+const PATH_PLACEHOLDER: &str = "{}";
+/// How often a running `--tag-fallback` command is checked for exit
+const WAIT_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Runs `command` in the shell for `album_dir`, which replaces `{}` in it, or is appended when there is no `{}`.
+/// The path is quoted, so that spaces and symbols in names are safe
+pub fn run_album_hook(command: &str, album_dir: &Path) -> Result<(), String> {
+    let command = with_path(command, album_dir);
+    let status = shell(&command).status().map_err(|err| { format!("Cannot run '{}': {}", command, err) })?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("'{}' failed with {}", command, status))
+    }
+}
+
+/// Runs `command` of `--tag-fallback` for `filepath` the same way as album hooks, expecting it to write missing tags.
+/// It doesn't get any input, so that it fails instead of waiting for an answer, and its output is hidden.
+/// The command is killed along with processes it started if it doesn't finish within `timeout`
+pub fn run_tag_fallback(command: &str, filepath: &Path, timeout: Duration) -> Result<(), String> {
+    let command = with_path(command, filepath);
+    let mut child = own_process_group(shell(&command))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|err| { format!("Cannot run '{}': {}", command, err) })?;
+
+    let started = Instant::now();
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() => return Ok(()),
+            Ok(Some(status)) => return Err(format!("'{}' failed with {}", command, status)),
+            Ok(None) if started.elapsed() >= timeout => {
+                // Killing fails only if the command has just exited, waiting reaps it either way
+                kill_process_group(&mut child);
+                let _ = child.wait();
+                return Err(format!("'{}' didn't finish in {} seconds and was killed", command, timeout.as_secs()));
+            },
+            Ok(None) => thread::sleep(WAIT_INTERVAL),
+            Err(err) => return Err(format!("Cannot wait for '{}': {}", command, err)),
+        }
+    }
+}
+
+/// Path replaces `{}` in `command`, or is appended when there is no `{}`
+fn with_path(command: &str, path: &Path) -> String {
+    let path = quote(&path.to_string_lossy());
+    if command.contains(PATH_PLACEHOLDER) {
+        command.replace(PATH_PLACEHOLDER, &path)
+    } else {
+        format!("{} {}", command, path)
+    }
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+/// Processes started by the shell stay in the group, so that all of them can be killed at once
+#[cfg(unix)]
+fn own_process_group(mut command: Command) -> Command {
+    use std::os::unix::process::CommandExt;
+
+    command.process_group(0);
+    command
+}
+
+#[cfg(windows)]
+fn own_process_group(command: Command) -> Command {
+    command
+}
+
+#[cfg(unix)]
+fn kill_process_group(child: &mut Child) {
+    // The group has the id of its first process, negative id sends the signal to the whole group
+    match libc::pid_t::try_from(child.id()) {
+        Ok(pid) => unsafe { libc::kill(-pid, libc::SIGKILL); },
+        Err(_) => { let _ = child.kill(); },
+    }
+}
+
+#[cfg(windows)]
+fn kill_process_group(child: &mut Child) {
+    let _ = child.kill();
+}
+
+#[cfg(unix)]
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// `"` can't be part of Windows file names, so it never has to be escaped
+#[cfg(windows)]
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value)
+}
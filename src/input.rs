@@ -0,0 +1,217 @@
+use std::{fs, io::{self, BufRead, BufReader, Read}, path::{Path, PathBuf}};
+
+use globset::GlobSet;
+
+/// Path standing for stdin, both among files and as `--files-from`
+pub const STDIN_PATH: &str = "-";
+/// Symbols that make a path a glob pattern, when no file has such name
+const GLOB_SYMBOLS: [char; 3] = [ '*', '?', '[' ];
+
+/// Expands passed directories into audio files they contain, recognized by lowercase `extensions`.
+/// Files and subdirectories matching `exclude` are skipped while walking.
+/// Explicitly passed files are kept as is, even if their extension is not supported or they are excluded
+pub fn collect_files(inputs: &[PathBuf], recursive: bool, extensions: &[String], exclude: &GlobSet) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+
+    for input in inputs {
+        if input.is_dir() {
+            if let Err(err) = walk_directory(input, recursive, extensions, exclude, &mut files) {
+                crate::print_warning(&format!("Cannot read directory '{}': {}", input.display(), err));
+            }
+        } else {
+            files.push(input.clone());
+        }
+    }
+
+    files
+}
+
+fn walk_directory(directory: &Path, recursive: bool, extensions: &[String], exclude: &GlobSet, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = fs::read_dir(directory)?
+        .map(|entry| { entry.map(|entry| { entry.path() }) })
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+
+    for entry in entries.into_iter().filter(|entry| { !is_excluded(entry, exclude) }) {
+        if entry.is_dir() {
+            if recursive {
+                if let Err(err) = walk_directory(&entry, recursive, extensions, exclude, files) {
+                    crate::print_warning(&format!("Cannot read directory '{}': {}", entry.display(), err));
+                }
+            }
+        } else if is_audio_file(&entry, extensions) {
+            files.push(entry);
+        }
+    }
+
+    Ok(())
+}
+
+/// Pattern may match either the whole path or just the name, so that `@eaDir` skips such directory anywhere
+fn is_excluded(path: &Path, exclude: &GlobSet) -> bool {
+    exclude.is_match(path) || path.file_name().is_some_and(|name| { exclude.is_match(name) })
+}
+
+/// Whether `path` has one of lowercase `extensions`
+pub fn is_audio_file(path: &Path, extensions: &[String]) -> bool {
+    path.extension()
+        .and_then(|ext| { ext.to_str() })
+        .map(|ext| { extensions.contains(&ext.to_lowercase()) })
+        .unwrap_or(false)
+}
+
+/// Replaces `-` among `files` with paths read from stdin, expands glob patterns among them and appends paths listed in `files_from`
+pub fn merge_file_lists(files: &[PathBuf], files_from: Option<&Path>, null_separated: bool) -> io::Result<Vec<PathBuf>> {
+    let mut merged = Vec::new();
+    for file in files {
+        if file.as_os_str() == STDIN_PATH {
+            merged.extend(read_file_list(Path::new(STDIN_PATH), null_separated)?);
+        } else {
+            merged.extend(expand_passed(file));
+        }
+    }
+    if let Some(list) = files_from {
+        merged.extend(read_file_list(list, null_separated)?);
+    }
+    Ok(merged)
+}
+
+/// Same as `merge_file_lists`, but lists are read lazily while the paths are taken, so that even millions of them
+/// don't have to be kept in memory. Lists are opened right away, read errors later on end the list with a warning
+pub fn stream_file_lists(files: &[PathBuf], files_from: Option<&Path>, null_separated: bool) -> io::Result<impl Iterator<Item = PathBuf> + Send> {
+    let mut lists = Vec::new();
+    for file in files.iter().filter(|file| { file.as_os_str() == STDIN_PATH }).take(1) {
+        lists.push(open_file_list(file, null_separated)?);
+    }
+    if let Some(list) = files_from {
+        lists.push(open_file_list(list, null_separated)?);
+    }
+
+    let passed: Vec<PathBuf> = files.iter()
+        .filter(|file| { file.as_os_str() != STDIN_PATH })
+        .flat_map(|file| { expand_passed(file) })
+        .collect();
+    Ok(passed.into_iter().chain(lists.into_iter().flatten()))
+}
+
+/// Paths of the list, read one by one
+struct FileListReader {
+    list: PathBuf,
+    reader: Box<dyn BufRead + Send>,
+    null_separated: bool,
+}
+
+impl Iterator for FileListReader {
+    type Item = PathBuf;
+
+    fn next(&mut self) -> Option<PathBuf> {
+        let separator = if self.null_separated { b'\0' } else { b'\n' };
+        let mut entry = Vec::new();
+        loop {
+            entry.clear();
+            match self.reader.read_until(separator, &mut entry) {
+                Ok(0) => return None,
+                Ok(_) => {},
+                Err(err) => {
+                    crate::print_warning(&format!("Cannot read list of files '{}': {}", self.list.display(), err));
+                    return None;
+                },
+            }
+            if entry.last() == Some(&separator) {
+                entry.pop();
+            }
+            if let Some(path) = list_entry(&entry, self.null_separated) {
+                return Some(path);
+            }
+        }
+    }
+}
+
+fn open_file_list(list: &Path, null_separated: bool) -> io::Result<FileListReader> {
+    let reader: Box<dyn BufRead + Send> = if list.as_os_str() == STDIN_PATH {
+        Box::new(BufReader::new(io::stdin()))
+    } else {
+        let file = fs::File::open(list).map_err(|err| { io::Error::new(err.kind(), format!("'{}': {}", list.display(), err)) })?;
+        Box::new(BufReader::new(file))
+    };
+    Ok(FileListReader { list: list.to_path_buf(), reader, null_separated })
+}
+
+/// Passed file itself, or files matching it if it's a glob pattern
+fn expand_passed(file: &Path) -> Vec<PathBuf> {
+    match expand_glob(file) {
+        Some(matched) => {
+            if matched.is_empty() {
+                crate::print_warning(&format!("No files matched '{}'", file.display()));
+            }
+            matched
+        },
+        None => vec![file.to_path_buf()],
+    }
+}
+
+/// Paths matching `pattern`, for shells that don't expand globs themselves, like the Windows one.
+/// `None` if `pattern` is an existing path or doesn't look like a pattern
+fn expand_glob(pattern: &Path) -> Option<Vec<PathBuf>> {
+    let pattern = pattern.to_str()?;
+    if !pattern.contains(GLOB_SYMBOLS) || Path::new(pattern).exists() {
+        return None;
+    }
+    // Invalid patterns are kept as paths, so that they are reported as missing files
+    let paths = glob::glob(pattern).ok()?;
+    Some(paths.filter_map(Result::ok).collect())
+}
+
+/// Reads paths from `list`, one per line or separated by NUL (e.g. from `find -print0`).
+/// Empty entries are ignored, as well as lines starting with `#` when paths are separated by lines
+fn read_file_list(list: &Path, null_separated: bool) -> io::Result<Vec<PathBuf>> {
+    // Paths may be any bytes, not only UTF-8
+    let mut content = Vec::new();
+    if list.as_os_str() == STDIN_PATH {
+        io::stdin().read_to_end(&mut content)?;
+    } else {
+        content = fs::read(list).map_err(|err| { io::Error::new(err.kind(), format!("'{}': {}", list.display(), err)) })?;
+    }
+
+    let separator = if null_separated { b'\0' } else { b'\n' };
+    let paths = content.split(|byte| { *byte == separator })
+        .filter_map(|entry| { list_entry(entry, null_separated) })
+        .collect();
+    Ok(paths)
+}
+
+/// Path of the list entry without its separator, `None` for ignored entries
+fn list_entry(entry: &[u8], null_separated: bool) -> Option<PathBuf> {
+    if null_separated {
+        return (!entry.is_empty()).then(|| { path_from_bytes(entry) });
+    }
+    if entry.iter().all(u8::is_ascii_whitespace) || entry.starts_with(b"#") {
+        return None;
+    }
+    Some(path_from_bytes(entry.strip_suffix(b"\r").unwrap_or(entry)))
+}
+
+#[cfg(unix)]
+pub(crate) fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+/// Paths of other systems are not just bytes, so lists are expected to be UTF-8
+#[cfg(not(unix))]
+pub(crate) fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+#[cfg(unix)]
+pub(crate) fn path_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+pub(crate) fn path_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
@@ -0,0 +1,1491 @@
+//! Puts audio files into directories named after their tags, e.g. `<Artist>/<Album>/<Title>.mp3`.
+//!
+//! `handle_file` does everything for a single file according to `Config`,
+//! sharing `RunState` with other files of the same run.
+
+mod album;
+pub mod albums;
+mod artist;
+pub mod check;
+mod checksum;
+pub mod duplicates;
+pub mod hook;
+mod encoding;
+mod error;
+pub mod input;
+pub mod logging;
+pub mod manifest;
+mod normalize;
+pub mod progress;
+pub mod report;
+pub mod resume;
+pub mod retag;
+pub mod space;
+pub mod tags;
+pub mod template;
+pub mod undo;
+pub mod watch;
+
+use std::{collections::{HashMap, HashSet}, fs, io::{self, BufRead, Write}, path::{Path, PathBuf}, str, sync::{atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering}, Mutex}, time::Duration};
+use clap::ValueEnum;
+use globset::GlobMatcher;
+
+use colored::Colorize;
+use regex::Regex;
+use unicode_normalization::UnicodeNormalization;
+
+pub use artist::{ArticleMode, DEFAULT_ARTICLES};
+pub use error::ManageError;
+pub use normalize::{normalize_path_entry, LetterCase, NormalizeMode, NormalizeOptions, UnicodeForm, FORBIDDEN_SYMBOLS};
+pub use tags::{ArtistSource, RequiredTags};
+use template::{Template, DEFAULT_TEMPLATE};
+
+/// Genre directory of files without genre, for `--genre-top`
+const UNKNOWN_GENRE: &str = "Unknown Genre";
+/// Extension of lyrics sidecar files, for `--write-lrc`
+const LYRICS_EXTENSION: &str = "lrc";
+/// Hex digits of hash appended to segments shortened by `--max-path-length`
+const TRUNCATION_HASH_LEN: usize = 6;
+/// Room kept by `--max-path-length` for suffixes of `--on-conflict rename`, enough for " (999)"
+const RENAME_SUFFIX_RESERVE: usize = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ConflictAction {
+    /// Replace existing file
+    Overwrite,
+    /// Leave existing file untouched and don't copy the new one
+    Skip,
+    /// Append ' (1)', ' (2)', ... to the filename until a free name is found
+    Rename,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum DiscLayout {
+    /// 'CD<disc>' folder inside of the album folder
+    Subfolder,
+    /// 'd<disc>t<track>' prefix of the filename
+    Prefix,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ReflinkMode {
+    /// Clone when filesystem supports it, copy otherwise
+    Auto,
+    /// Fail if file can't be cloned
+    Always,
+    /// Always make a regular copy
+    Never,
+}
+
+/// What `--verify` does with a copy that doesn't match the original
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BadCopy {
+    Keep,
+    Delete,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum MultiArtist {
+    /// Use the first artist only
+    First,
+    /// Join all artists with --multi-artist-separator
+    Join,
+    /// Put file under every artist
+    Each,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ContentCheck {
+    /// Same size is enough, fast but may be fooled by different files of equal size
+    Size,
+    /// Same size and SHA-256 hash, reads both files
+    Hash,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ExtensionCase {
+    /// Keep extension of the source file as is
+    Preserve,
+    Lower,
+    Upper,
+}
+
+/// Options of organizing files, as set by the command line flags of the same name
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Directory where to put audio files
+    pub target_directory: PathBuf,
+    /// Artists matching a pattern go to its directory instead of `target_directory`, the first matching pattern wins
+    pub artist_roots: Vec<(GlobMatcher, PathBuf)>,
+    pub template: Template,
+    pub on_conflict: ConflictAction,
+    /// Leave files alone when their target exists and isn't older, regardless of `on_conflict`
+    pub skip_if_newer: bool,
+    /// Tell apart skipped targets that have the same content as the file
+    pub target_exists_ok: Option<ContentCheck>,
+    /// Only plan targets, without touching the disk
+    pub dry_run: bool,
+    /// Ask on stdin before putting each file
+    pub interactive: bool,
+    pub move_files: bool,
+    /// Rename files after their title within their own directory instead of putting them into `target_directory`
+    pub in_place: bool,
+    pub hardlink: bool,
+    pub symlink: bool,
+    /// Remember placed files, so that the run can be reverted with `rollback`
+    pub rollback_on_error: bool,
+    pub number_tracks: bool,
+    pub track_number_width: usize,
+    pub always_disc: bool,
+    pub disc_layout: DiscLayout,
+    pub case: Option<LetterCase>,
+    /// Letter case of target extensions, not affected by `case`
+    pub extension_case: ExtensionCase,
+    pub replacement_char: String,
+    pub normalize: NormalizeMode,
+    /// Composed and decomposed forms of the same text map to one path
+    pub unicode_form: UnicodeForm,
+    pub copy_cover: bool,
+    /// Save embedded lyrics as `.lrc` file next to the target
+    pub write_lrc: bool,
+    pub preserve_mtime: bool,
+    /// Copy content of symlinked files. Otherwise links are recreated, pointing to the same file
+    pub follow_symlinks: bool,
+    /// Copy-on-write cloning of copied files
+    pub reflink: ReflinkMode,
+    /// Compare checksums of copies with their originals, `None` trusts the copy
+    pub verify: Option<BadCopy>,
+    pub max_path_length: Option<usize>,
+    pub flatten: bool,
+    pub flatten_separator: String,
+    /// Name targets after the source file instead of the last template segment
+    pub keep_filename: bool,
+    pub singles_folder: Option<String>,
+    pub genre_top: bool,
+    /// Joins all genres instead of using the first one
+    pub genre_separator: Option<String>,
+    /// Put artists under directory of their first letter, e.g. 'B/The Beatles'
+    pub letter_buckets: bool,
+    /// Put files under directory of the first letter of their title instead of artist. Meant to be used with the title-only template
+    pub by_title_letter: bool,
+    /// Bucket of artists or titles which don't start with a letter
+    pub other_bucket: String,
+    pub split_by_format: bool,
+    pub fix_extension: bool,
+    pub artist_source: Option<ArtistSource>,
+    /// Use readable part of partially corrupt tags
+    pub allow_partial: bool,
+    /// Tags files are rejected without
+    pub required: Vec<tags::Required>,
+    /// Stop handling files after this many failed, 0 means never
+    pub max_errors: usize,
+    pub tag_version: tags::TagVersion,
+    /// Comment used as `{comment}`
+    pub comment_key: tags::CommentKey,
+    pub multi_artist: MultiArtist,
+    pub multi_artist_separator: String,
+    pub strip_featured: bool,
+    /// Strip leading articles like "The" from the artist used for directory name, or move them to the end
+    pub ignore_articles: Option<ArticleMode>,
+    /// Articles of `ignore_articles`, matched case-insensitively
+    pub articles: Vec<String>,
+    /// Strip edition and disc suffixes from album used for directory
+    pub clean_album: bool,
+    /// Add MusicBrainz release ID to album directories
+    pub album_mbid: bool,
+    /// Add bit depth and sample rate of lossless files to album directories
+    pub audio_suffix: bool,
+    /// Removed from album by `clean_album` in addition to the default suffixes
+    pub album_patterns: Vec<Regex>,
+    /// Use artist and album sort names for directories when tags have them
+    pub use_sort_names: bool,
+    pub compilations: bool,
+    pub compilation_folder: String,
+    pub dedup: bool,
+    pub write_missing_tags: bool,
+    /// Shell command run for files missing required tags, which are read again after it
+    pub tag_fallback: Option<String>,
+    /// Lowercase extension without dot, files with other ones are skipped, even if passed explicitly
+    pub same_ext_only: Option<String>,
+    /// `tag_fallback` is killed after running for so long
+    pub tag_fallback_timeout: Duration,
+    pub require_replaygain: bool,
+    pub warn_encoding: bool,
+    /// Bytes that must stay free on the target drive
+    pub min_free_space: Option<u64>,
+}
+
+impl Config {
+    /// Config with the same defaults as the command line: files are copied into `<Artist>/<Album>/<Title>`
+    pub fn new(target_directory: impl Into<PathBuf>) -> Config {
+        Config {
+            target_directory: target_directory.into(),
+            artist_roots: Vec::new(),
+            template: DEFAULT_TEMPLATE.parse().expect("Default template is valid"),
+            on_conflict: ConflictAction::Skip,
+            skip_if_newer: false,
+            target_exists_ok: None,
+            dry_run: false,
+            interactive: false,
+            move_files: false,
+            in_place: false,
+            hardlink: false,
+            symlink: false,
+            rollback_on_error: false,
+            number_tracks: false,
+            track_number_width: 2,
+            always_disc: false,
+            disc_layout: DiscLayout::Subfolder,
+            case: None,
+            extension_case: ExtensionCase::Preserve,
+            replacement_char: String::from("_"),
+            normalize: NormalizeMode::Windows,
+            unicode_form: UnicodeForm::Nfc,
+            copy_cover: false,
+            write_lrc: false,
+            preserve_mtime: true,
+            follow_symlinks: true,
+            reflink: ReflinkMode::Auto,
+            verify: None,
+            max_path_length: None,
+            flatten: false,
+            flatten_separator: String::from(" - "),
+            keep_filename: false,
+            singles_folder: None,
+            genre_top: false,
+            genre_separator: None,
+            letter_buckets: false,
+            by_title_letter: false,
+            other_bucket: String::from("#"),
+            split_by_format: false,
+            fix_extension: false,
+            artist_source: None,
+            allow_partial: false,
+            required: tags::Required::ALL.to_vec(),
+            max_errors: 0,
+            tag_version: tags::TagVersion::PreferV2,
+            comment_key: tags::CommentKey::default(),
+            multi_artist: MultiArtist::First,
+            multi_artist_separator: String::from(" & "),
+            strip_featured: false,
+            ignore_articles: None,
+            articles: artist::DEFAULT_ARTICLES.iter().map(|article| { article.to_string() }).collect(),
+            use_sort_names: false,
+            clean_album: false,
+            album_mbid: false,
+            audio_suffix: false,
+            album_patterns: Vec::new(),
+            compilations: false,
+            compilation_folder: String::from("Various Artists"),
+            dedup: false,
+            write_missing_tags: false,
+            tag_fallback: None,
+            same_ext_only: None,
+            tag_fallback_timeout: Duration::from_secs(60),
+            require_replaygain: false,
+            warn_encoding: false,
+            min_free_space: None,
+        }
+    }
+
+    pub fn normalize_options(&self) -> NormalizeOptions {
+        NormalizeOptions {
+            replacement: self.replacement_char.clone(),
+            mode: self.normalize,
+            unicode_form: self.unicode_form,
+            case: self.case,
+        }
+    }
+
+    /// Directory where files of `artist` are put
+    pub fn target_root(&self, artist: &str) -> &Path {
+        self.artist_roots.iter()
+            .find(|(pattern, _)| { pattern.is_match(artist) })
+            .map(|(_, root)| { root.as_path() })
+            .unwrap_or(&self.target_directory)
+    }
+
+    /// Target directory and all artist roots
+    pub fn roots(&self) -> impl Iterator<Item = &Path> {
+        self.artist_roots.iter()
+            .map(|(_, root)| { root.as_path() })
+            .chain([self.target_directory.as_path()])
+    }
+
+    /// Target directory or artist root `target` is put into, the deepest one if they are nested
+    pub fn root_of(&self, target: &Path) -> Option<&Path> {
+        self.roots()
+            .filter(|root| { target.starts_with(root) })
+            .max_by_key(|root| { root.components().count() })
+    }
+
+    pub fn read_options(&self) -> tags::ReadOptions {
+        tags::ReadOptions {
+            artist_source: self.artist_source,
+            singles_album: self.singles_folder.clone(),
+            missing_title: None,
+            allow_partial: self.allow_partial,
+            required: self.required.clone(),
+            tag_version: self.tag_version,
+            comment_key: self.comment_key.clone(),
+        }
+    }
+}
+
+/// Result of successfully handled file. Each variant holds the final target path
+pub enum Outcome {
+    /// File was copied or moved to the target directory
+    Transferred(PathBuf),
+    /// File was copied or moved over the existing one
+    Overwritten(PathBuf),
+    /// File was copied or moved under the different name, as target already existed
+    Renamed(PathBuf),
+    /// Target already exists, so file was left untouched
+    Skipped(PathBuf),
+    /// Target already exists and isn't older than the file, so file was left untouched
+    UpToDate(PathBuf),
+    /// Target already exists with the same content, so file was left untouched
+    AlreadyPresent(PathBuf),
+    /// User refused to put file to the target in interactive mode
+    Declined(PathBuf),
+    /// File was left untouched, as target drive does not have enough free space
+    NoSpace(PathBuf),
+    /// File with the same content is already at the held path, so file was left untouched
+    Duplicate(PathBuf),
+    /// Dry run: file would be put to the target path
+    Planned(PathBuf),
+    /// File doesn't have the extension of `--same-ext-only`, so it was left untouched. Holds the file itself, as there is no target
+    OtherExtension(PathBuf),
+}
+
+impl Outcome {
+    pub fn target(&self) -> &Path {
+        match self {
+            Outcome::Transferred(target)
+            | Outcome::Overwritten(target)
+            | Outcome::Renamed(target)
+            | Outcome::Skipped(target)
+            | Outcome::UpToDate(target)
+            | Outcome::AlreadyPresent(target)
+            | Outcome::Declined(target)
+            | Outcome::NoSpace(target)
+            | Outcome::Duplicate(target)
+            | Outcome::Planned(target)
+            | Outcome::OtherExtension(target) => target,
+        }
+    }
+
+    /// Path the file was or would be put to, `None` when it was skipped before the target was known
+    pub fn destination(&self) -> Option<&Path> {
+        match self {
+            Outcome::OtherExtension(_) => None,
+            _ => Some(self.target()),
+        }
+    }
+
+    pub fn is_transferred(&self) -> bool {
+        matches!(self, Outcome::Transferred(_) | Outcome::Overwritten(_) | Outcome::Renamed(_))
+    }
+
+    /// File was deliberately left untouched
+    pub fn is_skipped(&self) -> bool {
+        matches!(self, Outcome::Skipped(_) | Outcome::UpToDate(_) | Outcome::AlreadyPresent(_) | Outcome::Declined(_) | Outcome::NoSpace(_) | Outcome::Duplicate(_) | Outcome::OtherExtension(_))
+    }
+}
+
+/// Level set by `--verbose`, global as it's needed deep inside of file handling
+static VERBOSITY: AtomicU8 = AtomicU8::new(0);
+
+/// Sets how much `print_verbose` logs, 0 being only the most important messages
+pub fn set_verbosity(level: u8) {
+    VERBOSITY.store(level, Ordering::Relaxed);
+}
+
+/// Target paths already taken by files of the current run.
+/// Parallel workers may resolve the same target before any of them finishes copying
+#[derive(Default)]
+struct ClaimedTargets(Mutex<HashSet<PathBuf>>);
+
+/// Directories created or found existing during the run, so that files of one album don't create them over and over
+#[derive(Default)]
+struct CreatedDirs(Mutex<HashSet<PathBuf>>);
+
+/// File put to the target during the run, kept for `--rollback-on-error`
+struct Placed {
+    source: PathBuf,
+    target: PathBuf,
+    moved: bool,
+}
+
+/// State shared by all files of the current run
+#[derive(Default)]
+pub struct RunState {
+    claimed_targets: ClaimedTargets,
+    /// Set when the rest of files must not be handled
+    aborted: AtomicBool,
+    /// Filled only with `--rollback-on-error`, including saved covers
+    placed: Mutex<Vec<Placed>>,
+    /// Content hash to the first source with such content and its target, for `--dedup`
+    contents: Mutex<HashMap<String, (PathBuf, PathBuf)>>,
+    created_dirs: CreatedDirs,
+    /// Files that failed to be put to at least one target
+    failed_files: AtomicUsize,
+    /// Directories that didn't exist before the run, created for its targets
+    new_dirs: Mutex<HashSet<PathBuf>>,
+}
+
+impl RunState {
+    fn abort(&self) {
+        self.aborted.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        self.aborted.load(Ordering::Relaxed)
+    }
+
+    /// `--max-errors` stopped the run
+    pub fn is_error_limit_reached(&self, config: &Config) -> bool {
+        config.max_errors > 0 && self.failed_files.load(Ordering::Relaxed) >= config.max_errors
+    }
+
+    /// Remembers that `source` with `hash` goes to `target`.
+    /// Returns target of another source with the same content, if there is one
+    fn claim_content(&self, hash: &str, source: &Path, target: &Path) -> Option<PathBuf> {
+        let mut contents = self.contents.lock().unwrap();
+        match contents.get(hash) {
+            Some((original_source, original_target)) if original_source != source => Some(original_target.clone()),
+            Some(_) => None,
+            None => {
+                contents.insert(hash.to_string(), (source.to_path_buf(), target.to_path_buf()));
+                None
+            },
+        }
+    }
+
+    /// Creates parent directory of `target`, unless it was already done during the run.
+    /// Failed attempts aren't remembered, so the next file tries again and reports its own error
+    fn create_parent_dir(&self, target: &Path) -> Result<(), ManageError> {
+        let Some(parent_dir) = target.parent() else {
+            return create_parent_dir(target);
+        };
+        if self.created_dirs.0.lock().unwrap().contains(parent_dir) {
+            return Ok(());
+        }
+        let created = create_missing_dirs(parent_dir)
+            .map_err(|io_err| { ManageError::io(format!("Cannot create directory '{}'", parent_dir.display()), io_err) })?;
+        self.new_dirs.lock().unwrap().extend(created);
+        self.created_dirs.0.lock().unwrap().insert(parent_dir.to_path_buf());
+        Ok(())
+    }
+
+    /// Amount of directories created by the run, not counting the ones that already existed
+    pub fn new_dir_count(&self) -> usize {
+        self.new_dirs.lock().unwrap().len()
+    }
+
+    /// Directory may be removed while the run goes on, then it has to be created again
+    fn forget_parent_dir(&self, target: &Path) {
+        if let Some(parent_dir) = target.parent() {
+            self.created_dirs.0.lock().unwrap().remove(parent_dir);
+        }
+    }
+
+    /// Sources moved during the run, recorded only with `--rollback-on-error`
+    pub fn moved_sources(&self) -> Vec<PathBuf> {
+        self.placed.lock().unwrap().iter()
+            .filter(|placed| { placed.moved })
+            .map(|placed| { placed.source.clone() })
+            .collect()
+    }
+
+    fn add_placed(&self, source: &Path, target: &Path, moved: bool) {
+        self.placed.lock().unwrap().push(Placed { source: source.to_path_buf(), target: target.to_path_buf(), moved });
+    }
+}
+
+enum Confirmation {
+    Yes,
+    No,
+    Quit,
+}
+
+fn confirm(source: &Path, target: &Path) -> Confirmation {
+    print!("{} -> {} [y/N/q] ", source.display(), target.display());
+    // Prompt is shown anyway, even if flushing fails
+    io::stdout().flush().ok();
+
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer).is_err() {
+        return Confirmation::Quit;
+    }
+
+    match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => Confirmation::Yes,
+        "q" | "quit" => Confirmation::Quit,
+        _ => Confirmation::No,
+    }
+}
+
+/// Creates target directory and checks it's writable, so that a wrong target fails once instead of for every file
+pub fn check_target_directory(target_directory: &Path) -> io::Result<()> {
+    fs::create_dir_all(target_directory)?;
+
+    let probe = target_directory.join(format!(".{}-write-check", env!("CARGO_PKG_NAME")));
+    fs::File::create(&probe)?;
+    fs::remove_file(&probe)
+}
+
+/// Results of putting the file to every target: there is more than one with `--multi-artist each`
+pub fn handle_file(filepath: &Path, config: &Config, state: &RunState) -> Vec<Result<Outcome, Vec<ManageError>>> {
+    let results = handle_file_targets(filepath, config, state);
+    if results.iter().any(Result::is_err) {
+        state.failed_files.fetch_add(1, Ordering::Relaxed);
+        // The run is going to be rolled back, so there is no point in handling the rest
+        if config.rollback_on_error || state.is_error_limit_reached(config) {
+            state.abort();
+        }
+    }
+    results
+}
+
+fn handle_file_targets(filepath: &Path, config: &Config, state: &RunState) -> Vec<Result<Outcome, Vec<ManageError>>> {
+    if state.is_aborted() {
+        return Vec::new();
+    }
+    // Checked before anything is read, so that unexpected files aren't even opened
+    if let Some(only_extension) = &config.same_ext_only {
+        let extension = filepath.extension().map(|ext| { ext.to_string_lossy().to_lowercase() });
+        if extension.as_ref() != Some(only_extension) {
+            return vec![Ok(Outcome::OtherExtension(filepath.to_path_buf()))];
+        }
+    }
+
+    let mut read_options = config.read_options();
+    if config.keep_filename {
+        // Title isn't used for the file name then, so it's not required
+        read_options.missing_title = filepath.file_stem().map(|stem| { stem.to_string_lossy().into_owned() });
+    }
+
+    let mut extension = filepath.extension().and_then(|ext| { ext.to_str() }).map(String::from);
+    if let Some(detected) = tags::detect_format(filepath).filter(|detected| { !tags::matches_extension(detected, extension.as_deref()) }) {
+        print_warning(&format!("'{}' is actually {} file, not matching its extension", filepath.display(), detected.to_uppercase()));
+        if config.fix_extension {
+            extension = Some(detected.to_string());
+        }
+    }
+
+    if config.write_missing_tags && !config.dry_run {
+        match tags::write_missing_album_artist(filepath) {
+            Ok(Some(album_artist)) => print_verbose(0, filepath, &format!("Album artist was missing, wrote '{}'", album_artist)),
+            Ok(None) => print_verbose(2, filepath, "No missing tags to write"),
+            Err(err) => print_warning(&format!("Missing tags of '{}' weren't written: {}", filepath.display(), format_error(&err))),
+        }
+    }
+
+    let tags = tags::read_tags(filepath, &read_options)
+        .or_else(|errors| { read_with_fallback(filepath, errors, &read_options, config) })
+        .inspect(|tags| {
+            if config.warn_encoding {
+                warn_garbled_tags(filepath, tags);
+            }
+        })
+        .and_then(|tags| {
+            if config.require_replaygain && !tags.has_replaygain() {
+                return Err(vec![ManageError::missing_tag("ReplayGain track or album gain")]);
+            }
+            Ok(tags)
+        });
+    let mut tags = match tags {
+        Ok(tags) => tags,
+        Err(errors) => return vec![Err(errors)],
+    };
+    if config.audio_suffix || config.template.uses_audio_properties() {
+        match tags::read_audio_properties(filepath) {
+            Ok(audio) => tags.audio = audio,
+            Err(err) => print_warning(&format!("Audio properties of '{}' weren't read: {}", filepath.display(), err)),
+        }
+    }
+    print_verbose(1, filepath, &format!("Tags: {:?}", tags));
+
+    let source_hash = if config.dedup {
+        match checksum::sha256_file(filepath) {
+            Ok(hash) => Some(hash),
+            Err(err) => return vec![Err(vec![ManageError::io("Cannot compute checksum of the file", err)])],
+        }
+    } else {
+        None
+    };
+
+    let tags_per_target = tags_per_target(tags, config);
+    let last_idx = tags_per_target.len() - 1;
+    let mut results = Vec::new();
+    for (idx, tags) in tags_per_target.iter().enumerate() {
+        // Quitting in interactive mode leaves the rest of artists alone as well
+        if state.is_aborted() {
+            break;
+        }
+        results.push(generate_target_path(filepath, extension.as_deref(), tags, config).and_then(|target_path| {
+            if let Some(duplicate) = source_hash.as_deref().and_then(|hash| { find_duplicate(filepath, hash, &target_path, state) }) {
+                print_verbose(2, filepath, &format!("Same content is already at '{}'", duplicate.display()));
+                return Ok(Outcome::Duplicate(duplicate));
+            }
+            place_file(filepath, target_path, config, state, idx == last_idx)
+        }));
+    }
+    results
+}
+
+/// Target paths `source` with `tags` would be put to, without touching the disk. Used to try templates out
+pub fn explain_targets(source: &Path, tags: RequiredTags, config: &Config) -> Vec<Result<PathBuf, Vec<ManageError>>> {
+    let extension = source.extension().and_then(|ext| { ext.to_str() });
+    tags_per_target(tags, config).iter()
+        .map(|tags| { generate_target_path(source, extension, tags, config) })
+        .collect()
+}
+
+/// Tags of every target of the file, after all options changing them are applied
+fn tags_per_target(tags: RequiredTags, config: &Config) -> Vec<RequiredTags> {
+    // Only the title names the file then
+    if config.in_place {
+        return vec![tags];
+    }
+    let tags = add_audio_suffix(add_album_id(clean_album(apply_sort_names(join_genres(tags, config), config), config), config), config);
+    split_by_artist(file_under_compilation(tags, config), config)
+}
+
+/// Applies `--genre-separator`
+fn join_genres(tags: RequiredTags, config: &Config) -> RequiredTags {
+    match &config.genre_separator {
+        Some(separator) if tags.genres.len() > 1 => {
+            // Resolved ID3v1 references may repeat genres written as text
+            let mut genres: Vec<&str> = Vec::new();
+            for genre in &tags.genres {
+                if !genres.contains(&genre.as_str()) {
+                    genres.push(genre);
+                }
+            }
+            let genre = genres.join(separator);
+            RequiredTags { genre: Some(genre), ..tags }
+        },
+        _ => tags,
+    }
+}
+
+/// Applies `--use-sort-names` to artist and album, the title stays as displayed
+fn apply_sort_names(tags: RequiredTags, config: &Config) -> RequiredTags {
+    if !config.use_sort_names {
+        return tags;
+    }
+
+    let (artist, artists) = match &tags.artist_sort {
+        Some(artist_sort) => (artist_sort.clone(), vec![artist_sort.clone()]),
+        None => (tags.artist.clone(), tags.artists.clone()),
+    };
+    let album = tags.album_sort.clone().unwrap_or_else(|| { tags.album.clone() });
+    RequiredTags { artist, artists, album, ..tags }
+}
+
+/// Applies `--clean-album`, keeping the original album for `{full_album}`
+fn clean_album(tags: RequiredTags, config: &Config) -> RequiredTags {
+    if !config.clean_album {
+        return tags;
+    }
+
+    let album = album::clean_album(&tags.album, &config.album_patterns);
+    if album == tags.album {
+        return tags;
+    }
+    RequiredTags { full_album: Some(tags.album.clone()), album, ..tags }
+}
+
+/// Applies `--album-mbid`, so that editions of the same album don't end up in one directory. Albums without the ID are kept
+fn add_album_id(tags: RequiredTags, config: &Config) -> RequiredTags {
+    match (&tags.album_id, config.album_mbid) {
+        (Some(album_id), true) => RequiredTags { album: album::with_album_id(&tags.album, album_id), ..tags },
+        _ => tags,
+    }
+}
+
+/// Applies `--audio-suffix`. Lossy files don't have bit depth and keep the album as is
+fn add_audio_suffix(tags: RequiredTags, config: &Config) -> RequiredTags {
+    match tags.audio.suffix() {
+        Some(suffix) if config.audio_suffix => RequiredTags { album: album::with_audio_suffix(&tags.album, &suffix), ..tags },
+        _ => tags,
+    }
+}
+
+/// Applies `--compilations`: compilation tracks go under the same artist, keeping their own artist in the title
+fn file_under_compilation(tags: RequiredTags, config: &Config) -> RequiredTags {
+    if !config.compilations || !tags.compilation {
+        return tags;
+    }
+
+    let track_artist = tags.track_artist.as_deref().map(|artist| {
+        artist.split('\0').filter(|artist| { !artist.is_empty() }).collect::<Vec<_>>().join(&config.multi_artist_separator)
+    });
+    let title = match track_artist {
+        Some(track_artist) if !track_artist.is_empty() => format!("{} - {}", track_artist, tags.title),
+        _ => tags.title.clone(),
+    };
+    let artist = config.compilation_folder.clone();
+    RequiredTags { artists: vec![artist.clone()], artist, title, artist_sort: None, ..tags }
+}
+
+/// Applies `--multi-artist` to the artist used for the path, producing tags for each target.
+/// Featured artists are stripped from each artist separately
+fn split_by_artist(tags: RequiredTags, config: &Config) -> Vec<RequiredTags> {
+    // Articles are handled after featured artists are cut off, so that the letter bucket is taken from the result
+    let prepare = |artist: &str| {
+        let stripped = if config.strip_featured { artist::strip_featured(artist) } else { artist };
+        match config.ignore_articles {
+            Some(mode) => artist::handle_article(stripped, &config.articles, mode),
+            None => stripped.to_string(),
+        }
+    };
+    // Original value is kept for `{full_artist}`
+    let full_artist = |artist: &str, prepared: &str| { (artist != prepared).then(|| { artist.to_string() }) };
+
+    match config.multi_artist {
+        _ if tags.artists.len() < 2 => {
+            let artist = prepare(&tags.artist);
+            let full_artist = full_artist(&tags.artist, &artist);
+            vec![RequiredTags { artist, full_artist, ..tags }]
+        },
+        MultiArtist::First => {
+            let artist = prepare(&tags.artists[0]);
+            let full_artist = full_artist(&tags.artists[0], &artist);
+            vec![RequiredTags { artist, full_artist, ..tags }]
+        },
+        MultiArtist::Join => {
+            let artist = tags.artists.iter().map(|artist| { prepare(artist) }).collect::<Vec<_>>().join(&config.multi_artist_separator);
+            let full_artist = full_artist(&tags.artists.join(&config.multi_artist_separator), &artist);
+            vec![RequiredTags { artist, full_artist, ..tags }]
+        },
+        MultiArtist::Each => tags.artists.iter()
+            .map(|each| {
+                let artist = prepare(each);
+                RequiredTags { full_artist: full_artist(each, &artist), artist, ..tags.clone() }
+            })
+            .collect(),
+    }
+}
+
+/// Existing target with the same content, or target of another source of the run with the same content
+fn find_duplicate(source: &Path, hash: &str, target: &Path, state: &RunState) -> Option<PathBuf> {
+    if target.is_file() && checksum::sha256_file(target).is_ok_and(|target_hash| { target_hash == hash }) {
+        return Some(target.to_path_buf());
+    }
+    state.claim_content(hash, source, target)
+}
+
+/// Puts file to the generated target. With `--move` the file is moved only to the last target, and copied to the rest
+fn place_file(filepath: &Path, target_path: PathBuf, config: &Config, state: &RunState, is_last_target: bool) -> Result<Outcome, Vec<ManageError>> {
+    // Target written earlier in the same run belongs to another file, so it's a regular conflict
+    let is_claimed = state.claimed_targets.0.lock().unwrap().contains(&target_path);
+    if config.skip_if_newer && !is_claimed && is_up_to_date(filepath, &target_path) {
+        print_verbose(2, filepath, "Target already exists and is up to date, skipping");
+        return Ok(Outcome::UpToDate(target_path));
+    }
+
+    let outcome = if config.in_place && is_same_file(filepath, &target_path) {
+        if filepath.file_name() == target_path.file_name() {
+            print_verbose(2, filepath, "File already has the name of its title");
+            return Ok(Outcome::AlreadyPresent(target_path));
+        }
+        // Name differs only in case on case-insensitive filesystem, the existing target is the file itself
+        state.claimed_targets.0.lock().unwrap().insert(target_path.clone());
+        Outcome::Transferred(target_path)
+    } else {
+        resolve_conflict(target_path, config.on_conflict, config.max_path_length, &state.claimed_targets)
+    };
+    match &outcome {
+        Outcome::Overwritten(_) => print_verbose(2, filepath, "Target already exists, overwriting it"),
+        Outcome::Renamed(target) => print_verbose(2, filepath, &format!("Target already exists, using '{}' instead", target.display())),
+        Outcome::Skipped(_) => print_verbose(2, filepath, "Target already exists, skipping"),
+        _ => print_verbose(2, filepath, "Target is free"),
+    }
+    if let Outcome::Skipped(target) = outcome {
+        if let (Some(check), false) = (config.target_exists_ok, is_claimed) {
+            if is_same_content(filepath, &target, check) {
+                print_verbose(2, filepath, "Existing target has the same content");
+                return Ok(Outcome::AlreadyPresent(target));
+            }
+        }
+        return Ok(Outcome::Skipped(target));
+    }
+
+    if config.dry_run {
+        return Ok(Outcome::Planned(outcome.target().to_path_buf()));
+    }
+
+    if config.interactive {
+        match confirm(filepath, outcome.target()) {
+            Confirmation::Yes => {},
+            Confirmation::No => return Ok(Outcome::Declined(outcome.target().to_path_buf())),
+            Confirmation::Quit => {
+                state.abort();
+                return Ok(Outcome::Declined(outcome.target().to_path_buf()));
+            },
+        }
+    }
+
+    if let Some(margin) = config.min_free_space {
+        // Artist roots may be on other disks
+        let root = config.root_of(outcome.target()).unwrap_or(&config.target_directory);
+        let is_link = config.hardlink || config.symlink || is_preserved_link(filepath, config);
+        let is_rename = config.in_place || (config.move_files && is_last_target && space::is_same_filesystem(filepath, root));
+        if !is_link && !is_rename {
+            if let Err(available) = space::check_free_space(filepath, root, margin) {
+                print_warning(&format!(
+                    "Not enough free space for '{}': {} available, {} must be kept free",
+                    filepath.display(), space::format_size(available), space::format_size(margin)
+                ));
+                return Ok(Outcome::NoSpace(outcome.target().to_path_buf()));
+            }
+        }
+    }
+
+    // Source file might be gone after move, so cover is read beforehand
+    let cover = if config.copy_cover { tags::read_cover(filepath) } else { None };
+    let lyrics = if config.write_lrc { tags::read_lyrics(filepath) } else { None };
+
+    // Source has to stay in place until it's put under every artist
+    let is_move = (config.move_files || config.in_place) && is_last_target;
+    state.create_parent_dir(outcome.target()).map_err(|e| { vec![e] })?;
+    let transfer_result = if is_move {
+        move_file(filepath, outcome.target(), config.preserve_mtime, config.verify)
+    } else if config.hardlink {
+        hard_link_file(filepath, outcome.target())
+    } else if config.symlink {
+        symlink_file(filepath, outcome.target())
+    } else if is_preserved_link(filepath, config) {
+        print_verbose(2, filepath, "File is a symbolic link, recreating it instead of copying its content");
+        symlink_file(filepath, outcome.target())
+    } else {
+        copy_file(filepath, outcome.target(), config.preserve_mtime, config.reflink)
+            .and_then(|()| { config.verify.map_or(Ok(()), |bad_copy| { verify_copy(filepath, outcome.target(), bad_copy) }) })
+    };
+    if transfer_result.is_err() {
+        state.forget_parent_dir(outcome.target());
+    }
+    transfer_result
+        .map(|()| {
+            if config.rollback_on_error {
+                state.add_placed(filepath, outcome.target(), is_move);
+            }
+            if let Some(lyrics) = lyrics {
+                if let (Some(lrc_path), true) = (save_lyrics(&lyrics, outcome.target()), config.rollback_on_error) {
+                    state.add_placed(filepath, &lrc_path, false);
+                }
+            }
+            if let (Some(cover), Some(album_dir)) = (cover, outcome.target().parent()) {
+                if let (Some(cover_path), true) = (save_cover(&cover, album_dir), config.rollback_on_error) {
+                    state.add_placed(filepath, &cover_path, false);
+                }
+            }
+            outcome
+        })
+        .map_err(|e| { vec![e] })
+}
+
+/// Runs `--tag-fallback` when the only problem of the file is missing tags, and reads them again.
+/// Errors of the first read stay if the command fails
+fn read_with_fallback(filepath: &Path, errors: Vec<ManageError>, read_options: &tags::ReadOptions, config: &Config) -> Result<RequiredTags, Vec<ManageError>> {
+    let is_missing_tags = errors.iter().all(|err| { matches!(err, ManageError::MissingTag { .. }) });
+    let command = match &config.tag_fallback {
+        Some(command) if is_missing_tags => command,
+        _ => return Err(errors),
+    };
+    if config.dry_run {
+        // The command is expected to write tags, which dry run must not do
+        print_verbose(1, filepath, "Tags are missing, --tag-fallback isn't run in dry run");
+        return Err(errors);
+    }
+
+    print_verbose(1, filepath, &format!("Tags are missing, running --tag-fallback '{}'", command));
+    match hook::run_tag_fallback(command, filepath, config.tag_fallback_timeout) {
+        Ok(()) => tags::read_tags(filepath, read_options)
+            .inspect(|_| { print_verbose(0, filepath, "Missing tags were written by --tag-fallback"); }),
+        Err(err) => {
+            print_warning(&format!("Tag fallback for '{}' failed: {}", filepath.display(), err));
+            Err(errors)
+        },
+    }
+}
+
+/// Both paths lead to the same existing file
+fn is_same_file(source: &Path, target: &Path) -> bool {
+    match (source.canonicalize(), target.canonicalize()) {
+        (Ok(source), Ok(target)) => source == target,
+        _ => false,
+    }
+}
+
+/// Source is a symbolic link, which is recreated in the target instead of being copied with `--follow-symlinks false`
+fn is_preserved_link(filepath: &Path, config: &Config) -> bool {
+    !config.follow_symlinks && fs::symlink_metadata(filepath).is_ok_and(|metadata| { metadata.file_type().is_symlink() })
+}
+
+fn warn_garbled_tags(filepath: &Path, tags: &RequiredTags) {
+    let values = [
+        ("artist", Some(tags.artist.as_str())),
+        ("album", Some(tags.album.as_str())),
+        ("title", Some(tags.title.as_str())),
+        ("genre", tags.genre.as_deref()),
+    ];
+    for (name, value) in values {
+        if let Some(value) = value.filter(|value| { encoding::looks_garbled(value) }) {
+            print_warning(&format!("Suspicious encoding of {} in '{}': {:?}", name, filepath.display(), value));
+        }
+    }
+}
+
+/// `extension` is appended to the file name, it may differ from the one of `source` with `--fix-extension`
+pub fn generate_target_path(source: &Path, extension: Option<&str>, tags: &RequiredTags, config: &Config) -> Result<PathBuf, Vec<ManageError>> {
+    if config.in_place {
+        let mut filename = normalize_path_entry(&tags.title, &config.normalize_options());
+        push_extension(&mut filename, extension, config.extension_case);
+        let target_path = source.with_file_name(filename);
+        print_verbose(1, source, &format!("Target path: {}", target_path.display()));
+        return Ok(target_path);
+    }
+
+    // File name of the template isn't even expanded when the original one is kept, so that its tags aren't needed
+    let (filename, directories) = if config.keep_filename {
+        (None, config.template.expand_directories(tags)?)
+    } else {
+        let mut segments = config.template.expand(tags)?;
+        let filename = segments.pop();
+        (filename, segments)
+    };
+    let root = config.target_root(&tags.artist);
+
+    let normalize_options = config.normalize_options();
+    // Generated entries and kept file names are not affected by `--case`
+    let exact_options = NormalizeOptions { case: None, ..config.normalize_options() };
+    let normalize = |entry: &str| { normalize_path_entry(entry, &normalize_options) };
+    let mut normalized_segments = Vec::new();
+    if config.genre_top {
+        normalized_segments.push(normalize(tags.genre.as_deref().unwrap_or(UNKNOWN_GENRE)));
+    }
+    let bucket = if config.by_title_letter {
+        Some(letter_bucket(&tags.title, &config.other_bucket))
+    } else if config.letter_buckets {
+        // Sort name puts "The Beatles" under "B". It's only known for the artist tag as a whole, not for split artists
+        let sort_name = tags.artist_sort.as_deref().filter(|_| { tags.artists.len() < 2 });
+        let artist = match sort_name {
+            Some(sort_name) if config.strip_featured => artist::strip_featured(sort_name),
+            Some(sort_name) => sort_name,
+            None => tags.artist.as_str(),
+        };
+        Some(letter_bucket(artist, &config.other_bucket))
+    } else {
+        None
+    };
+    if let Some(bucket) = bucket {
+        // Bucket is always in upper case
+        normalized_segments.push(normalize_path_entry(&bucket, &exact_options));
+    }
+    normalized_segments.extend(directories.iter().map(|directory| { normalize(directory.as_str()) }));
+
+    // Disc number is only needed to tell apart tracks of multi-disc albums
+    let is_multi_disc = tags.disc.is_some_and(|disc| { disc > 1 }) || tags.total_discs.is_some_and(|total| { total > 1 });
+    let disc = tags.disc.filter(|_| { is_multi_disc || config.always_disc });
+
+    let mut full_target_filename = match filename {
+        Some(filename) => normalize(&filename),
+        // Name is kept as is, apart from what has to be replaced to make it valid
+        None => normalize_path_entry(&source.file_stem().unwrap_or_default().to_string_lossy(), &exact_options),
+    };
+    let track_prefix = match (config.number_tracks, tags.track) {
+        (true, Some(track)) => Some(format!("{:0width$}", track, width = config.track_number_width)),
+        _ => None,
+    };
+    let filename_prefix = match (disc, config.disc_layout) {
+        (Some(disc), DiscLayout::Prefix) => {
+            let track = tags.track.map(|track| { format!("t{:0width$}", track, width = config.track_number_width) });
+            Some(format!("d{}{}", disc, track.unwrap_or_default()))
+        },
+        (Some(disc), DiscLayout::Subfolder) => {
+            normalized_segments.push(normalize(&format!("CD{}", disc)));
+            track_prefix
+        },
+        (None, _) => track_prefix,
+    };
+    if let Some(prefix) = filename_prefix {
+        full_target_filename = format!("{} {}", prefix, full_target_filename);
+    }
+    if config.flatten {
+        normalized_segments.push(full_target_filename);
+        full_target_filename = normalized_segments.join(&config.flatten_separator);
+        normalized_segments.clear();
+    }
+    if config.split_by_format {
+        normalized_segments.insert(0, normalize_path_entry(&tags::format_label(source), &exact_options));
+    }
+    push_extension(&mut full_target_filename, extension, config.extension_case);
+    normalized_segments.push(full_target_filename);
+    if let Some(max_length) = config.max_path_length {
+        let extension_len = extension.map(|ext| { ext.chars().count() + 1 }).unwrap_or(0);
+        // Renamed target is longer than the planned one
+        let max_length = if config.on_conflict == ConflictAction::Rename { max_length.saturating_sub(RENAME_SUFFIX_RESERVE) } else { max_length };
+        fit_path_length(root, &mut normalized_segments, extension_len, max_length).map_err(|err| { vec![err] })?;
+    }
+    print_verbose(1, source, &format!("Path segments: {:?}", normalized_segments));
+
+    let mut result_path = PathBuf::new();
+    result_path.push(root);
+    result_path.extend(normalized_segments);
+    print_verbose(1, source, &format!("Target path: {}", result_path.display()));
+    Ok(result_path)
+}
+
+fn push_extension(filename: &mut String, extension: Option<&str>, case: ExtensionCase) {
+    if let Some(ext) = extension {
+        filename.push('.');
+        match case {
+            ExtensionCase::Preserve => filename.push_str(ext),
+            ExtensionCase::Lower => filename.push_str(&ext.to_lowercase()),
+            ExtensionCase::Upper => filename.push_str(&ext.to_uppercase()),
+        }
+    }
+}
+
+/// Upper case first letter of artist or title for `--letter-buckets`, without accents, so that "Édith Piaf" goes under "E".
+/// Names starting with digit or symbol go under `other_bucket`
+fn letter_bucket(name: &str, other_bucket: &str) -> String {
+    match name.trim_start().nfd().next() {
+        Some(letter) if letter.is_alphabetic() => letter.to_uppercase().next().unwrap_or(letter).to_string(),
+        _ => other_bucket.to_string(),
+    }
+}
+
+/// Shortens the longest segments until the whole path fits into `max_length` characters.
+/// Extension of the last segment (`extension_len` characters, including the dot) is never touched
+fn fit_path_length(target_directory: &Path, segments: &mut [String], extension_len: usize, max_length: usize) -> Result<(), ManageError> {
+    let last_idx = segments.len() - 1;
+    let reserved_len = |idx: usize| { if idx == last_idx { extension_len } else { 0 } };
+    // Lengths of segments without extension, shortened step by step
+    let original_lengths: Vec<usize> = segments.iter().enumerate()
+        .map(|(idx, segment)| { segment.chars().count() - reserved_len(idx) })
+        .collect();
+    let mut lengths = original_lengths.clone();
+    // Separators are counted along with segments
+    let fixed_length = target_directory.as_os_str().to_string_lossy().chars().count() + segments.len() + extension_len;
+    // At least one original character is kept in front of "~<hash>"
+    let min_length = TRUNCATION_HASH_LEN + 2;
+
+    loop {
+        let length = fixed_length + lengths.iter().sum::<usize>();
+        if length <= max_length {
+            break;
+        }
+
+        let longest = (0..lengths.len())
+            .filter(|idx| { lengths[*idx] > min_length })
+            .max_by_key(|idx| { lengths[*idx] })
+            .ok_or_else(|| {
+                ManageError::InvalidTarget { message: format!("Target path is longer than {} characters even after shortening", max_length) }
+            })?;
+        let longest_len = lengths[longest];
+        let second_len = (0..lengths.len()).filter(|idx| { *idx != longest }).map(|idx| { lengths[idx] }).max().unwrap_or(0);
+
+        // Longest segment is cut down to the next one first, so that one segment is not cut to nothing
+        let mut new_len = longest_len.saturating_sub(length - max_length).max(second_len).max(min_length);
+        if new_len == longest_len {
+            new_len -= 1;
+        }
+        lengths[longest] = new_len;
+    }
+
+    for (idx, segment) in segments.iter_mut().enumerate() {
+        if lengths[idx] < original_lengths[idx] {
+            let kept: String = segment.chars().take(lengths[idx] - TRUNCATION_HASH_LEN - 1).collect();
+            let extension: String = segment.chars().skip(original_lengths[idx]).collect();
+            *segment = format!("{}~{}{}", kept.trim_end(), checksum::short_hash(segment, TRUNCATION_HASH_LEN), extension);
+        }
+    }
+    Ok(())
+}
+
+/// Compares source with the existing target for `--target-exists-ok`
+fn is_same_content(source: &Path, target: &Path, check: ContentCheck) -> bool {
+    let size = |path: &Path| { fs::metadata(path).map(|metadata| { metadata.len() }).ok() };
+    let is_same_size = size(source).is_some_and(|source_size| { size(target) == Some(source_size) });
+    match check {
+        ContentCheck::Size => is_same_size,
+        ContentCheck::Hash => is_same_size && match (checksum::sha256_file(source), checksum::sha256_file(target)) {
+            (Ok(source_hash), Ok(target_hash)) => source_hash == target_hash,
+            _ => false,
+        },
+    }
+}
+
+/// Target exists and was modified not earlier than the source, for `--skip-if-newer`
+fn is_up_to_date(source: &Path, target: &Path) -> bool {
+    let modified = |path: &Path| { fs::metadata(path).and_then(|metadata| { metadata.modified() }) };
+    match (modified(source), modified(target)) {
+        (Ok(source_mtime), Ok(target_mtime)) => target_mtime >= source_mtime,
+        _ => false,
+    }
+}
+
+/// Applies `--on-conflict` policy to the target path
+/// Renamed targets stay within `max_length` characters, their name is shortened when the suffix doesn't fit into the reserved room
+fn resolve_conflict(target: PathBuf, action: ConflictAction, max_length: Option<usize>, claimed_targets: &ClaimedTargets) -> Outcome {
+    let mut claimed = claimed_targets.0.lock().unwrap();
+    let is_taken = |path: &Path| { path.exists() || claimed.contains(path) };
+
+    let outcome = if !is_taken(&target) {
+        Outcome::Transferred(target)
+    } else {
+        match action {
+            ConflictAction::Overwrite => Outcome::Overwritten(target),
+            ConflictAction::Skip => return Outcome::Skipped(target),
+            ConflictAction::Rename => {
+                let stem = target.file_stem().and_then(|stem| { stem.to_str() }).unwrap_or_default().to_string();
+                let ext = target.extension().and_then(|ext| { ext.to_str() }).map(|ext| { format!(".{}", ext) }).unwrap_or_default();
+
+                let candidate = (1..)
+                    .map(|idx| {
+                        let candidate = target.with_file_name(format!("{} ({}){}", stem, idx, ext));
+                        let overflow = max_length.map_or(0, |max_length| { path_length(&candidate).saturating_sub(max_length) });
+                        if overflow == 0 {
+                            return candidate;
+                        }
+                        let kept: String = stem.chars().take(stem.chars().count().saturating_sub(overflow)).collect();
+                        target.with_file_name(format!("{} ({}){}", kept.trim_end(), idx, ext))
+                    })
+                    .find(|candidate| { !is_taken(candidate) })
+                    .unwrap();
+                Outcome::Renamed(candidate)
+            }
+        }
+    };
+
+    claimed.insert(outcome.target().to_path_buf());
+    outcome
+}
+
+fn path_length(path: &Path) -> usize {
+    path.as_os_str().to_string_lossy().chars().count()
+}
+
+fn create_parent_dir(target: &Path) -> Result<(), ManageError> {
+    target.parent()
+        .ok_or_else(|| { ManageError::InvalidTarget { message: format!("Unexpected error while copying file to target '{}'", target.display()) } })
+        .and_then(|parent_dir| {
+            fs::create_dir_all(parent_dir).map_err(|io_err| { ManageError::io(format!("Cannot create directory '{}'", parent_dir.display()), io_err) })
+        })
+}
+
+/// Same as `fs::create_dir_all`, but returns the directories it created. `create_dir_all` doesn't tell apart
+/// existing directories, so missing ones are created one by one. A directory created by another worker in the meantime
+/// isn't returned
+fn create_missing_dirs(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let missing: Vec<&Path> = dir.ancestors()
+        .take_while(|ancestor| { !ancestor.as_os_str().is_empty() && !ancestor.is_dir() })
+        .collect();
+
+    let mut created = Vec::new();
+    for missing_dir in missing.iter().rev() {
+        match fs::create_dir(missing_dir) {
+            Ok(()) => created.push(missing_dir.to_path_buf()),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists && missing_dir.is_dir() => (),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(created)
+}
+
+/// Parent directory of `target` must exist, same for other transfers
+fn copy_file(source: &Path, target: &Path, preserve_mtime: bool, reflink: ReflinkMode) -> Result<(), ManageError> {
+    copy_content(source, target, reflink)?;
+    if preserve_mtime {
+        copy_mtime(source, target);
+    }
+    Ok(())
+}
+
+/// Reads `target` back and compares its SHA-256 with the one of `source`. Both are hashed in chunks,
+/// so big files aren't loaded into memory
+fn verify_copy(source: &Path, target: &Path, bad_copy: BadCopy) -> Result<(), ManageError> {
+    print_verbose(2, source, "Verifying the copy");
+    let hashes = checksum::sha256_file(source)
+        .and_then(|source_hash| { checksum::sha256_file(target).map(|target_hash| { (source_hash, target_hash) }) })
+        .map_err(|io_err| { ManageError::io(format!("Cannot verify copy '{}'", target.display()), io_err) })?;
+    if hashes.0 == hashes.1 {
+        return Ok(());
+    }
+
+    let kept = match bad_copy {
+        BadCopy::Keep => "it was kept",
+        BadCopy::Delete => match fs::remove_file(target) {
+            Ok(()) => "it was deleted",
+            Err(_) => "it couldn't be deleted",
+        },
+    };
+    Err(ManageError::io(
+        format!("Copy '{}' doesn't match the original, {}", target.display(), kept),
+        io::Error::new(io::ErrorKind::InvalidData, "checksums differ"),
+    ))
+}
+
+/// Clones the file if `reflink` allows it, falling back to the regular copy unless clone is required
+fn copy_content(source: &Path, target: &Path, reflink: ReflinkMode) -> Result<(), ManageError> {
+    if reflink != ReflinkMode::Never {
+        match clone_file(source, target) {
+            Ok(()) => {
+                print_verbose(2, source, "Cloned file");
+                return Ok(());
+            },
+            Err(io_err) if reflink == ReflinkMode::Always => return Err(ManageError::io(transfer_context("Failed to clone", source, target), io_err)),
+            Err(io_err) => print_verbose(2, source, &format!("File can't be cloned ({}), copying it instead", io_err)),
+        }
+    }
+
+    print_verbose(2, source, "Copying file");
+    fs::copy(source, target)
+        .map(|_| {})
+        .map_err(|io_err| { ManageError::io(transfer_context("Failed to copy", source, target), io_err) })
+}
+
+/// Names both ends of the failed transfer, e.g. "Failed to copy 'in/a.mp3' to 'out/Artist/Album/Title.mp3'"
+fn transfer_context(action: &str, source: &Path, target: &Path) -> String {
+    format!("{} '{}' to '{}'", action, source.display(), target.display())
+}
+
+/// Copy-on-write clone of the file. Clone can't replace existing target, so it's put next to it first
+fn clone_file(source: &Path, target: &Path) -> io::Result<()> {
+    if !target.exists() {
+        return reflink_copy::reflink(source, target);
+    }
+
+    let file_name = target.file_name().map(|name| { name.to_string_lossy().into_owned() }).unwrap_or_default();
+    let temporary = target.with_file_name(format!(".{}.clone", file_name));
+    reflink_copy::reflink(source, &temporary)?;
+    fs::rename(&temporary, target).inspect_err(|_| {
+        fs::remove_file(&temporary).ok();
+    })
+}
+
+/// Failure is only reported as a warning, since the file itself was copied successfully
+fn copy_mtime(source: &Path, target: &Path) {
+    let result = fs::metadata(source)
+        .map(|metadata| { filetime::FileTime::from_last_modification_time(&metadata) })
+        .and_then(|mtime| { filetime::set_file_mtime(target, mtime) });
+
+    if let Err(err) = result {
+        print_warning(&format!("Modification time of '{}' wasn't preserved due to error: {}", target.display(), err));
+    }
+}
+
+/// Moves file back and forth outside of the regular run, creating parent directory of `target`
+fn move_file_to(source: &Path, target: &Path, preserve_mtime: bool) -> Result<(), ManageError> {
+    create_parent_dir(target)?;
+    move_file(source, target, preserve_mtime, None)
+}
+
+/// `verify` only applies when the file has to be copied to another device, the original is kept if the copy is bad
+fn move_file(source: &Path, target: &Path, preserve_mtime: bool, verify: Option<BadCopy>) -> Result<(), ManageError> {
+    print_verbose(2, source, "Renaming file");
+    match fs::rename(source, target) {
+        Ok(()) => Ok(()),
+        // Rename works only within one filesystem
+        Err(io_err) if io_err.kind() == io::ErrorKind::CrossesDevices => {
+            print_verbose(2, source, "Target is on another device, falling back to copy and remove");
+            // Clones can't cross filesystems either
+            copy_file(source, target, preserve_mtime, ReflinkMode::Never)
+                .and_then(|()| { verify.map_or(Ok(()), |bad_copy| { verify_copy(source, target, bad_copy) }) })
+                .and_then(|()| {
+                    fs::remove_file(source)
+                        .map_err(|io_err| { ManageError::io(format!("File was copied to '{}', but original file '{}' wasn't removed", target.display(), source.display()), io_err) })
+                })
+        },
+        Err(io_err) => Err(ManageError::io(transfer_context("Failed to move", source, target), io_err)),
+    }
+}
+
+/// Returns whether source file was removed
+pub fn remove_source(file: &Path) -> bool {
+    match fs::remove_file(file) {
+        Ok(()) => true,
+        Err(err) => {
+            print_warning(&format!("Original file wan't removed due to error: {}", err));
+            false
+        },
+    }
+}
+
+/// Reverts everything placed during the run, latest first.
+/// Returns amount of reverted files and of all placed ones
+pub fn rollback(state: &RunState) -> (usize, usize) {
+    let placed = std::mem::take(&mut *state.placed.lock().unwrap());
+    let new_dirs = state.new_dirs.lock().unwrap();
+    let mut rolled_back = 0;
+    for placed in placed.iter().rev() {
+        let result = if placed.moved {
+            move_file_to(&placed.target, &placed.source, true).map_err(|err| { format_error(&err) })
+        } else {
+            fs::remove_file(&placed.target).map_err(|err| { err.to_string() })
+        };
+        match result {
+            Ok(()) => rolled_back += 1,
+            Err(err) => print_warning(&format!("Cannot roll back '{}': {}", placed.target.display(), err)),
+        }
+        if let Some(dir) = placed.target.parent() {
+            remove_new_dirs(dir, &new_dirs);
+        }
+    }
+    (rolled_back, placed.len())
+}
+
+/// Removes emptied directories of removed source file, leaving target directory, artist roots and everything inside of them intact
+pub fn prune_source_dirs(source: &Path, config: &Config) {
+    let source_dir = match source.parent() {
+        Some(dir) if dir.as_os_str().is_empty() => Path::new("."),
+        Some(dir) => dir,
+        None => return,
+    };
+    let Ok(source_dir) = source_dir.canonicalize() else {
+        return;
+    };
+    // Roots that don't exist can't be removed
+    let roots: Vec<PathBuf> = config.roots().filter_map(|root| { root.canonicalize().ok() }).collect();
+    if roots.iter().any(|root| { source_dir.starts_with(root) }) {
+        return;
+    }
+
+    let roots: Vec<&Path> = roots.iter().map(PathBuf::as_path).collect();
+    remove_empty_dirs(&source_dir, &roots);
+}
+
+/// Removes `directory` and its parents while they are empty and were created by the run, which leaves earlier directories intact
+fn remove_new_dirs(directory: &Path, new_dirs: &HashSet<PathBuf>) {
+    let mut current = Some(directory);
+    while let Some(dir) = current.filter(|dir| { new_dirs.contains(*dir) }) {
+        // Removal fails if the directory isn't empty
+        if fs::remove_dir(dir).is_err() {
+            break;
+        }
+        print_verbose(2, dir, "Removed empty directory");
+        current = dir.parent();
+    }
+}
+
+/// Removes `directory` and its parents while they are empty, stopping at any of `roots`
+fn remove_empty_dirs(directory: &Path, roots: &[&Path]) {
+    let mut current = Some(directory);
+    while let Some(dir) = current {
+        if roots.contains(&dir) {
+            break;
+        }
+        let is_empty = fs::read_dir(dir).map(|mut entries| { entries.next().is_none() }).unwrap_or(false);
+        // Removal fails if something appears in the directory meanwhile
+        if !is_empty || fs::remove_dir(dir).is_err() {
+            break;
+        }
+        print_verbose(2, dir, "Removed empty directory");
+        current = dir.parent();
+    }
+}
+
+/// Returns path of the cover, if it was created
+fn save_cover(cover: &tags::Cover, album_dir: &Path) -> Option<PathBuf> {
+    let cover_path = album_dir.join(format!("cover.{}", cover.extension()));
+    // `create_new` keeps the cover written by another file of the same album
+    let write_result = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&cover_path)
+        .and_then(|mut file| { file.write_all(&cover.data) });
+
+    match write_result {
+        Ok(()) => return Some(cover_path),
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {},
+        Err(err) => print_warning(&format!("Cover '{}' wasn't saved due to error: {}", cover_path.display(), err)),
+    }
+    None
+}
+
+/// Writes `<target name>.lrc` next to the target. Returns its path, if it was written
+fn save_lyrics(lyrics: &str, target: &Path) -> Option<PathBuf> {
+    let lrc_path = target.with_extension(LYRICS_EXTENSION);
+    // `create_new` keeps lyrics, which were there before, e.g. edited by hand
+    let write_result = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&lrc_path)
+        .and_then(|mut file| { file.write_all(lyrics.as_bytes()) });
+
+    match write_result {
+        Ok(()) => return Some(lrc_path),
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => print_verbose(1, &lrc_path, "Lyrics file already exists, not overwritten"),
+        Err(err) => print_warning(&format!("Lyrics '{}' weren't saved due to error: {}", lrc_path.display(), err)),
+    }
+    None
+}
+
+pub fn format_error(err: &ManageError) -> String {
+    err.to_string()
+}
+
+fn hard_link_file(source: &Path, target: &Path) -> Result<(), ManageError> {
+    print_verbose(2, source, "Creating hard link");
+
+    // Existing target is only reached with `--on-conflict overwrite`
+    if target.exists() {
+        fs::remove_file(target).map_err(|io_err| { ManageError::io(format!("Failed to remove existing target '{}'", target.display()), io_err) })?;
+    }
+
+    fs::hard_link(source, target).map_err(|io_err| {
+        if io_err.kind() == io::ErrorKind::CrossesDevices {
+            ManageError::io("Hard links can't cross filesystems, target directory must be on the same device as the original file", io_err)
+        } else {
+            ManageError::io(transfer_context("Failed to hard link", source, target), io_err)
+        }
+    })
+}
+
+fn symlink_file(source: &Path, target: &Path) -> Result<(), ManageError> {
+    print_verbose(2, source, "Creating symbolic link");
+    // Relative source would make the link depend on the current working directory
+    let source = fs::canonicalize(source)
+        .map_err(|io_err| { ManageError::io("Cannot resolve absolute path of the original file", io_err) })?;
+
+    // Existing target is only reached with `--on-conflict overwrite`
+    if target.symlink_metadata().is_ok() {
+        fs::remove_file(target).map_err(|io_err| { ManageError::io(format!("Failed to remove existing target '{}'", target.display()), io_err) })?;
+    }
+
+    #[cfg(unix)]
+    let link_result = std::os::unix::fs::symlink(&source, target);
+    #[cfg(windows)]
+    let link_result = std::os::windows::fs::symlink_file(&source, target);
+
+    link_result.map_err(|io_err| {
+        // ERROR_PRIVILEGE_NOT_HELD
+        if cfg!(windows) && io_err.raw_os_error() == Some(1314) {
+            ManageError::io("Creating symbolic links requires administrator rights or enabled Developer Mode", io_err)
+        } else {
+            ManageError::io(transfer_context("Failed to symlink", &source, target), io_err)
+        }
+    })
+}
+
+pub fn print_verbose(level: u8, file: &Path, message: &str) {
+    log::log!(logging::verbose_level(level), "[{}] {}", file.display(), message);
+    if VERBOSITY.load(Ordering::Relaxed) >= level {
+        progress::suspend(|| { eprintln!("{} {}", format!("[{}]", file.display()).dimmed(), message) });
+    }
+}
+
+pub fn print_warning(message: &str) {
+    log::warn!("{}", message);
+    progress::suspend(|| { eprintln!("{} {}", "Warning!".yellow().bold(), message) });
+}
+
+/// File was put to the target, or would be in dry run
+pub fn is_success(result: &Result<Outcome, Vec<ManageError>>) -> bool {
+    matches!(result, Ok(Outcome::Transferred(_) | Outcome::Overwritten(_) | Outcome::Renamed(_) | Outcome::Planned(_)))
+}
@@ -0,0 +1,26 @@
+use std::{fs::OpenOptions, io::{self, Write}, path::Path};
+
+use log::LevelFilter;
+
+/// Appends timestamped entries to the file at `path`: outcome of every file and warnings,
+/// along with `print_verbose` messages up to `verbosity`. Console output is not affected
+pub fn init_log_file(path: &Path, verbosity: u8) -> io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let level = match verbosity {
+        0 => LevelFilter::Info,
+        1 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+
+    env_logger::Builder::new()
+        .target(env_logger::Target::Pipe(Box::new(file)))
+        .filter_level(level)
+        .format(|buf, record| { writeln!(buf, "{} {:<5} {}", buf.timestamp_millis(), record.level(), record.args()) })
+        .try_init()
+        .map_err(io::Error::other)
+}
+
+/// Level of `print_verbose` messages of the given verbosity
+pub fn verbose_level(level: u8) -> log::Level {
+    if level <= 1 { log::Level::Debug } else { log::Level::Trace }
+}
@@ -1,12 +1,18 @@
-use std::{path::{PathBuf}, fs, str, process::exit};
-use clap::{Parser, arg};
+mod config;
 
-use id3::{Tag, TagLike, Error, ErrorKind};
+use std::{collections::{BTreeMap, BTreeSet}, env, io::{self, IsTerminal}, num::NonZeroUsize, ops::ControlFlow, path::{Path, PathBuf}, process::exit, sync::{atomic::{AtomicBool, Ordering}, mpsc}, thread, time::Duration};
+use clap::{ArgAction, Parser, ValueEnum};
 
 use colored::Colorize;
+use globset::{Glob, GlobBuilder, GlobMatcher, GlobSetBuilder};
+use rayon::prelude::*;
+use regex::Regex;
 
-const FORBIDDEN_SYMBOLS: [char; 9] = [ '<', '>', ':', '\"', '/', '\\', '|', '?', '*' ];
-const RESERVED_WINDOWS_NAMES: [&str; 22] = [ "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9" ];
+use music_shelf_manager::{
+    albums, check, check_target_directory, duplicates, hook, resume, retag, format_error, handle_file, input, is_success, logging, manifest, print_warning, progress, prune_source_dirs,
+    remove_source, report, rollback, space, tags::{self, ArtistSource, CommentKey, Required, TagVersion}, template::{Template, DEFAULT_TEMPLATE, TITLE_LETTER_TEMPLATE}, undo, watch,
+    ArticleMode, BadCopy, Config, ConflictAction, ContentCheck, DiscLayout, ExtensionCase, LetterCase, ManageError, MultiArtist, NormalizeMode, Outcome, ReflinkMode, RunState, UnicodeForm, DEFAULT_ARTICLES, FORBIDDEN_SYMBOLS,
+};
 
 #[derive(Parser, Debug)]
 struct CliArgs {
@@ -14,180 +20,1115 @@ struct CliArgs {
     #[arg(short, long)]
     remove_source_file: bool,
 
+    /// Move files instead of copying them. Uses rename when possible, falls back to copy+remove across devices
+    #[arg(long = "move", conflicts_with = "remove_source_file")]
+    move_files: bool,
+
+    /// Rename files after their title tag within their own directory, e.g. 'Track 01.mp3' to 'Song.mp3',
+    /// ignoring artist, album and the template. Target directory isn't used
+    #[arg(long, conflicts_with_all = ["target_directory", "move_files", "remove_source_file", "hardlink", "symlink", "watch", "artist_root"])]
+    in_place: bool,
+
+    /// Create hard links to original files instead of copying them. Target must be on the same filesystem
+    #[arg(long, conflicts_with_all = ["remove_source_file", "move_files"])]
+    hardlink: bool,
+
+    /// Create symbolic links to original files instead of copying them
+    #[arg(long, conflicts_with_all = ["remove_source_file", "move_files", "hardlink"])]
+    symlink: bool,
+
+    /// Only show where files would be put, without copying or removing anything. Target directory doesn't have to exist
+    /// or be writable, and may be omitted to check tags and paths of a read-only library
+    #[arg(long, visible_alias = "test-only-tags")]
+    dry_run: bool,
+
+    /// What to do when a file already exists at the target path
+    #[arg(long, value_enum, value_name = "ACTION", default_value_t = ConflictAction::Skip)]
+    on_conflict: ConflictAction,
+
+    /// Skip files whose target already exists and was modified at the same time or later, whatever --on-conflict says
+    #[arg(long)]
+    skip_if_newer: bool,
+
+    /// Compare skipped files with existing targets, reporting identical ones as already present
+    /// and the rest as naming collisions
+    #[arg(long)]
+    target_exists_ok: bool,
+
+    /// How thoroughly --target-exists-ok compares files
+    #[arg(long, value_enum, value_name = "CHECK", default_value_t = ContentCheck::Hash)]
+    compare_by: ContentCheck,
+
+    /// Output format of the handling results
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// Amount of files handled in parallel. Defaults to the number of CPUs
+    #[arg(short, long, value_name = "N")]
+    jobs: Option<NonZeroUsize>,
+
+    /// Print intermediate steps of handling each file to stderr. Use twice for even more details
+    #[arg(short, long, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// Don't print lines of successfully handled files. Use twice to omit the summary as well
+    #[arg(short, long, action = ArgAction::Count, conflicts_with = "verbose")]
+    quiet: u8,
+
+    /// When to color the output. 'auto' colors it if it goes to a terminal and NO_COLOR is not set
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
+    /// Same as --color never
+    #[arg(long, conflicts_with = "color")]
+    no_color: bool,
+
+    /// Append timestamped outcome of every file and warnings to this file. --verbose adds intermediate steps to it as well
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<PathBuf>,
+
     /// Directory where to put audio files
-    #[arg(short, long, value_name = "DIRECTORY")]
-    target_directory: PathBuf,
+    #[arg(short, long, value_name = "DIRECTORY", required_unless_present_any = ["undo", "check", "report_duplicates_by_tag", "min_track_count", "retag_from_path", "explain_template", "dry_run", "in_place"])]
+    target_directory: Option<PathBuf>,
+
+    /// Put artists matching glob PATTERN under DIRECTORY instead of the target directory, e.g. 'Grateful Dead*=/mnt/archive'.
+    /// Matching is case-insensitive, the first matching pattern wins. May be used multiple times,
+    /// or given as a table in the config file
+    #[arg(long, value_name = "PATTERN=DIRECTORY", value_parser = parse_artist_root)]
+    artist_root: Vec<(GlobMatcher, PathBuf)>,
+
+    /// Pattern of the destination path relative to target directory.
+    /// Available placeholders: {artist}, {full_artist}, {album}, {full_album}, {title}, {year}, {track}, {disc}, {genre}, {comment},
+    /// {sample_rate} (in kHz), {bit_depth}, {channels}.
+    /// Numbers can be zero-padded, e.g. {track:02}. {year? - } adds the year and ' - ' only if there is a year
+    #[arg(long, value_name = "TEMPLATE", default_value = DEFAULT_TEMPLATE)]
+    template: Template,
+
+    /// Prefix filenames with the track number, when it's present in tags
+    #[arg(long)]
+    number_tracks: bool,
+
+    /// Amount of digits track number is zero-padded to with `--number-tracks`
+    #[arg(long, value_name = "WIDTH", default_value_t = 2)]
+    track_number_width: usize,
+
+    /// Put disc number into the path even for single-disc albums (when disc number is present)
+    #[arg(long)]
+    always_disc: bool,
+
+    /// How disc number of multi-disc albums is put into the path
+    #[arg(long, value_enum, value_name = "LAYOUT", default_value_t = DiscLayout::Subfolder)]
+    disc_layout: DiscLayout,
+
+    /// Change letter case of path entries, so that e.g. "The Beatles" and "THE BEATLES" end up in one directory.
+    /// On case-insensitive filesystems existing directory is reused as is, keeping its original case
+    #[arg(long, value_enum, value_name = "CASE")]
+    case: Option<LetterCase>,
+
+    /// Letter case of target file extensions, e.g. 'lower' turns '.MP3' into '.mp3'
+    #[arg(long, value_enum, value_name = "CASE", default_value_t = ExtensionCase::Preserve)]
+    extension_case: ExtensionCase,
+
+    /// Character put in place of symbols forbidden in file names. Empty value removes them
+    #[arg(long, value_name = "CHAR", default_value = "_", value_parser = parse_replacement_char)]
+    replacement_char: String,
+
+    /// Which file names are considered valid. Names normalized for POSIX may be unusable on Windows
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = NormalizeMode::Windows)]
+    normalize: NormalizeMode,
+
+    /// Unicode normalization of names, so that the same text written differently ends up in one directory
+    #[arg(long, value_enum, value_name = "FORM", default_value_t = UnicodeForm::Nfc)]
+    unicode_form: UnicodeForm,
+
+    /// Save embedded cover art as 'cover.<ext>' into album directory, unless it already exists
+    #[arg(long)]
+    copy_cover: bool,
+
+    /// Save embedded lyrics (ID3 SYLT/USLT, Vorbis LYRICS) as '.lrc' file next to the target, e.g. 'Title.lrc'.
+    /// Synced lyrics are saved with timestamps. Existing '.lrc' files are kept
+    #[arg(long)]
+    write_lrc: bool,
+
+    /// Don't copy modification time of original files to their copies
+    #[arg(long)]
+    no_preserve_mtime: bool,
+
+    /// Copy content of files that are symbolic links. With 'false' such files become links in the target directory,
+    /// pointing to the same file as the original link
+    #[arg(long, value_name = "BOOL", default_value_t = true, action = ArgAction::Set)]
+    follow_symlinks: bool,
+
+    /// Clone copied files with copy-on-write (btrfs, XFS, APFS, ReFS), like 'cp --reflink'
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ReflinkMode::Auto)]
+    reflink: ReflinkMode,
+
+    /// Read every copy back and compare its checksum with the original, reporting copies that differ.
+    /// Doubles reading, meant for flaky media. Sources of --move and --remove-source-file are kept if their copy is bad
+    #[arg(long)]
+    verify: bool,
+
+    /// Delete copies failing --verify instead of keeping them for inspection
+    #[arg(long, requires = "verify")]
+    delete_bad_copies: bool,
+
+    /// Shorten the longest path segments, so that the whole target path fits into LENGTH characters.
+    /// Shortened segments get a hash suffix to stay unique. Windows allows 260 characters by default.
+    /// With '--on-conflict rename' room is kept for the ' (N)' suffix of renamed files
+    #[arg(long, value_name = "LENGTH")]
+    max_path_length: Option<usize>,
+
+    /// Put all files right into target directory, joining path segments into the file name, e.g. 'Artist - Album - Title.mp3'
+    #[arg(long)]
+    flatten: bool,
+
+    /// Separator of path segments joined by --flatten
+    #[arg(long, value_name = "SEPARATOR", default_value = " - ", value_parser = parse_separator)]
+    flatten_separator: String,
+
+    /// Keep name of the original file instead of naming it after the title tag, which then isn't required.
+    /// Directories are still made from the template
+    #[arg(long)]
+    keep_filename: bool,
+
+    /// Put files without album tag into this directory instead of rejecting them, e.g. 'Artist/Singles/Title.mp3'.
+    /// Files with empty album tag are still handled as before
+    #[arg(long, value_name = "NAME")]
+    singles_folder: Option<String>,
+
+    /// Put files into top-level directory named after genre. Files without genre go to 'Unknown Genre'
+    #[arg(long)]
+    genre_top: bool,
+
+    /// Join all values of genre tag with this separator for --genre-top and {genre}, instead of using the first one
+    #[arg(long, value_name = "SEPARATOR", value_parser = parse_separator)]
+    genre_separator: Option<String>,
+
+    /// Put artists under directory of their first letter, e.g. 'B/The Beatles/Abbey Road/...'.
+    /// Artist sort name is used for the letter when tags have it
+    #[arg(long)]
+    letter_buckets: bool,
+
+    /// Put files into directory of the first letter of their title, without artist and album, e.g. 'C/Come Together.mp3'.
+    /// Titles often repeat, so it works best with '--on-conflict rename'
+    #[arg(long, conflicts_with_all = ["template", "letter_buckets"])]
+    by_title_letter: bool,
+
+    /// Directory used by --letter-buckets and --by-title-letter for names starting with digit or symbol
+    #[arg(long, value_name = "NAME", default_value = "#")]
+    other_bucket: String,
+
+    /// Put files into top-level directory named after their format, e.g. 'FLAC/Artist/Album/Title.flac'.
+    /// Goes above the genre directory of --genre-top and is kept even with --flatten
+    #[arg(long)]
+    split_by_format: bool,
+
+    /// Give targets the extension matching the real format, when content of the file doesn't match its extension
+    #[arg(long)]
+    fix_extension: bool,
+
+    /// Tag used as the artist. Defaults to album artist, falling back to artist
+    #[arg(long, value_enum, value_name = "SOURCE")]
+    artist_source: Option<ArtistSource>,
+
+    /// Use tags read before a corrupt ID3 frame instead of rejecting the file, as long as required tags are there.
+    /// Such files are reported with a warning
+    #[arg(long)]
+    allow_partial: bool,
+
+    /// Comma-separated tags files are rejected without, e.g. 'artist,title'.
+    /// Defaults to artist, album and title used by the template, other missing ones are left empty
+    #[arg(long, value_enum, value_name = "TAGS", value_delimiter = ',')]
+    require: Vec<Required>,
+
+    /// Which of ID3v2 and ID3v1 tags of MP3 files win when both are present. Fields missing in one are taken from the other
+    #[arg(long, value_enum, value_name = "VERSION", default_value_t = TagVersion::PreferV2)]
+    tag_version: TagVersion,
+
+    /// Comment used as {comment} placeholder, e.g. 'Library' or 'Library:eng' for the one of this description and language.
+    /// Defaults to the comment without description. FLAC files read the field of this name instead, e.g. LIBRARY
+    #[arg(long, value_name = "DESCRIPTION[:LANG]")]
+    comment_key: Option<CommentKey>,
+
+    /// What to do with artist tag storing several artists separated with NUL or ';'
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = MultiArtist::First)]
+    multi_artist: MultiArtist,
+
+    /// Separator of artists joined by '--multi-artist join'
+    #[arg(long, value_name = "SEPARATOR", default_value = " & ", value_parser = parse_separator)]
+    multi_artist_separator: String,
+
+    /// Remove featured artists ("feat.", "ft.", "featuring") from the artist used for directory name
+    #[arg(long)]
+    strip_featured: bool,
+
+    /// Strip leading articles ('The', 'A', 'An') from the artist used for directory name, or move them to the end
+    /// like 'Beatles, The'. Applied after --strip-featured and before --letter-buckets.
+    /// The artist as written in tags is still available as '{full_artist}' placeholder
+    #[arg(long, value_enum, value_name = "MODE")]
+    ignore_articles: Option<ArticleMode>,
+
+    /// Comma-separated articles of --ignore-articles, e.g. 'The,A,An,Die,Der,Das,Les'
+    #[arg(long, value_name = "LIST", value_delimiter = ',', default_values = DEFAULT_ARTICLES, requires = "ignore_articles")]
+    articles: Vec<String>,
+
+    /// Name artist and album directories after sort name tags (TSOP/TSO2, TSOA, ARTISTSORT, ALBUMSORT),
+    /// e.g. 'Beatles, The', falling back to the displayed names. File names keep the displayed title
+    #[arg(long)]
+    use_sort_names: bool,
+
+    /// Strip edition and disc suffixes like '(Deluxe Edition)', '(Remastered 2011)' or '[Disc 1]' from album directory.
+    /// The original album is still available as '{full_album}' placeholder
+    #[arg(long)]
+    clean_album: bool,
+
+    /// Regular expression also removed from album by --clean-album, anywhere in it. May be used multiple times
+    #[arg(long, value_name = "REGEX", requires = "clean_album", value_parser = parse_regex)]
+    clean_album_pattern: Vec<Regex>,
+
+    /// Add the beginning of MusicBrainz release ID to album directories, e.g. 'Album [0c8c9b3e]', to tell apart reissues
+    /// and regional editions of the same album. Albums without the ID keep plain names
+    #[arg(long)]
+    album_mbid: bool,
+
+    /// Add bit depth and sample rate of lossless files to album directories, e.g. 'Album [24-96]', to keep hi-res editions apart.
+    /// Lossy files have no bit depth and keep plain album names
+    #[arg(long)]
+    audio_suffix: bool,
+
+    /// Put tracks of compilations (compilation flag set or album artist 'Various Artists') into one directory,
+    /// e.g. 'Various Artists/Album/Artist - Title.mp3'
+    #[arg(long)]
+    compilations: bool,
+
+    /// Name of the artist directory used by --compilations
+    #[arg(long, value_name = "NAME", default_value = "Various Artists")]
+    compilation_folder: String,
+
+    /// Skip files, which content is identical to existing target or to another file of the run
+    #[arg(long)]
+    dedup: bool,
+
+    /// Write album artist copied from artist into source files, which have only the latter.
+    /// Existing values are never changed. Supported for MP3 and FLAC files
+    #[arg(long)]
+    write_missing_tags: bool,
+
+    /// Treat files without ReplayGain track or album gain as errors
+    #[arg(long)]
+    require_replaygain: bool,
+
+    /// Warn about tag values that look wrongly decoded, e.g. 'Ã©' instead of 'é'. Files are handled anyway
+    #[arg(long)]
+    warn_encoding: bool,
+
+    /// Skip files that would leave less than SIZE free on the target drive, e.g. '500M' or '2G'.
+    /// Not checked for links and moves within the same drive
+    #[arg(long, value_name = "SIZE", value_parser = space::parse_size)]
+    min_free_space: Option<u64>,
+
+    /// Ask for confirmation before putting each file to its target
+    #[arg(short, long, conflicts_with = "dry_run")]
+    interactive: bool,
+
+    /// Shell command run once for every album directory that got new files, after all files are handled,
+    /// e.g. to rescan them in media server. '{}' is replaced with the album path, which is appended if there is no '{}'
+    #[arg(long, value_name = "CMD")]
+    on_album_complete: Option<String>,
+
+    /// Shell command run for files missing required tags, e.g. a lookup script writing them, before they are read again.
+    /// '{}' is replaced with the file path, which is appended if there is no '{}'. Not run in dry run
+    #[arg(long, value_name = "CMD")]
+    tag_fallback: Option<String>,
+
+    /// Seconds --tag-fallback may run for a single file before it's killed
+    #[arg(long, value_name = "SECONDS", default_value_t = 60, requires = "tag_fallback")]
+    tag_fallback_timeout: u64,
 
-    /// List of original files, that needs to be managed
+    /// Handle only files with this extension, e.g. 'flac', skipping the rest even if they are passed explicitly.
+    /// Guards against careless globs picking up cue sheets, logs or images
+    #[arg(long, value_name = "EXT", value_parser = parse_extension)]
+    same_ext_only: Option<String>,
+
+    /// Descend into subdirectories of passed directories
+    #[arg(short = 'R', long)]
+    recursive: bool,
+
+    /// Comma-separated extensions of files picked up from passed directories, e.g. 'mp3,flac'.
+    /// Defaults to all supported formats. Explicitly passed files are handled regardless
+    #[arg(long, value_name = "EXT", value_delimiter = ',', value_parser = parse_extension)]
+    extensions: Vec<String>,
+
+    /// Skip files and directories matching GLOB while walking passed directories, e.g. '@eaDir' or '**/.AppleDouble'.
+    /// Matched against both the whole path and the name. Explicitly passed files are never excluded. May be used multiple times
+    #[arg(long, value_name = "GLOB", value_parser = parse_glob)]
+    exclude: Vec<Glob>,
+
+    /// After source file is removed by --remove-source-file or --move, remove its directory and parents while they are empty
+    #[arg(long)]
+    prune_empty_dirs: bool,
+
+    /// Print target path of every successfully handled file to stdout, moving status lines to stderr
+    #[arg(long, conflicts_with = "format")]
+    print_targets: bool,
+
+    /// Revert the whole run if any file fails: placed files are removed, moved files are put back.
+    /// Sources are removed by --remove-source-file only after all files succeed
+    #[arg(long, conflicts_with = "dry_run")]
+    rollback_on_error: bool,
+
+    /// Stop after this many files failed, e.g. when the target drive is gone. Handled files stay where they are. 0 means never
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    max_errors: usize,
+
+    /// Write CSV file with source, destination, action and status of every file
+    #[arg(long, value_name = "PATH")]
+    manifest: Option<PathBuf>,
+
+    /// Record every completed file in this state file, and skip files recorded there by previous runs.
+    /// Files are recorded right after they are handled, so an interrupted run can be picked up where it stopped
+    #[arg(long, value_name = "STATE_FILE", conflicts_with_all = ["dry_run", "rollback_on_error", "undo", "check", "report_duplicates_by_tag", "min_track_count", "retag_from_path", "explain_template"])]
+    resume: Option<PathBuf>,
+
+    /// Revert the run recorded in the manifest: remove copies and links, put moved files back.
+    /// Files changed after the run are left untouched. Emptied directories are removed, up to target directory if it's passed
+    #[arg(long, value_name = "MANIFEST", conflicts_with_all = ["files", "manifest", "dry_run", "interactive"])]
+    undo: Option<PathBuf>,
+
+    /// Only read tags of all files and report which of artist, album and title are missing, without putting files anywhere
+    #[arg(long, conflicts_with_all = ["manifest", "dry_run", "interactive", "undo", "print_targets"])]
+    check: bool,
+
+    /// Only read tags of all files and list groups of files with the same artist, album and title,
+    /// e.g. the same song in different formats, without putting files anywhere
+    #[arg(long, conflicts_with_all = ["manifest", "dry_run", "interactive", "undo", "print_targets", "check"])]
+    report_duplicates_by_tag: bool,
+
+    /// Only read tags of all files and list albums with fewer files than their total tracks tag, e.g. "3/12",
+    /// or than this number when the tag isn't set, without putting files anywhere
+    #[arg(long, value_name = "N", conflicts_with_all = ["manifest", "dry_run", "interactive", "undo", "print_targets", "check", "report_duplicates_by_tag"])]
+    min_track_count: Option<usize>,
+
+    /// Only write artist, album and title taken from the "Artist/Album/Title" layout of file paths into their tags,
+    /// without putting files anywhere. Tags that are already set are kept unless --force-retag is passed
+    #[arg(long, conflicts_with_all = ["manifest", "dry_run", "interactive", "undo", "print_targets", "check", "report_duplicates_by_tag", "min_track_count"])]
+    retag_from_path: bool,
+
+    /// Overwrite tags that are already set with --retag-from-path
+    #[arg(long, requires = "retag_from_path")]
+    force_retag: bool,
+
+    /// Only print where a file with tags given by --set would be put with the template and other options, without any real files.
+    /// Paths are relative unless target directory is passed
+    #[arg(long, conflicts_with_all = ["files", "files_from", "undo", "check", "report_duplicates_by_tag", "min_track_count", "retag_from_path", "watch", "manifest", "interactive"])]
+    explain_template: bool,
+
+    /// Tag of the imaginary file of --explain-template, e.g. 'artist=The Beatles'. Tags are artist, album, title, year, track,
+    /// total_tracks, disc, total_discs, genre, artist_sort, album_sort, album_id, compilation,
+    /// sample_rate (in Hz), bit_depth, channels and comment, 'ext' sets the file extension (mp3 by default)
+    #[arg(long = "set", value_name = "TAG=VALUE", value_parser = parse_tag_value, requires = "explain_template")]
+    set: Vec<(String, String)>,
+
+    /// Keep watching the directory and organize audio files already in it and appearing in it, once they are completely written.
+    /// Combine with --move to empty the inbox. Stops on Ctrl-C
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["files", "files_from", "interactive", "rollback_on_error", "undo", "check", "report_duplicates_by_tag", "min_track_count", "retag_from_path", "prune_empty_dirs"])]
+    watch: Option<PathBuf>,
+
+    /// Config file with default values of options. Defaults to <config dir>/music-shelf-manager/config.toml
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Read more files to manage from this list, one per line. Blank lines and lines starting with '#' are ignored.
+    /// '-' reads the list from stdin
+    #[arg(long, value_name = "PATH", conflicts_with = "undo")]
+    files_from: Option<PathBuf>,
+
+    /// Paths in --files-from and stdin lists are separated with NUL instead of new lines, as printed by 'find -print0'
+    #[arg(long)]
+    null: bool,
+
+    /// Handle files while their list is still read from --files-from or stdin, instead of reading it whole first,
+    /// so that even millions of paths don't take up memory. Status lines are aligned to a fixed width then
+    #[arg(long, conflicts_with_all = ["interactive", "check", "report_duplicates_by_tag", "min_track_count", "retag_from_path", "watch", "undo", "explain_template"])]
+    stream: bool,
+
+    /// List of original files, that needs to be managed. Directories are expanded into audio files they contain.
+    /// '-' reads list of files from stdin. Glob patterns like '*.mp3' are expanded, when the shell doesn't do it
     files: Vec<PathBuf>,
 }
 
-struct RequiredTags {
-    pub artist: String,
-    pub album: String,
-    pub title: String,
+impl CliArgs {
+    /// Only `--undo`, `--check`, `--report-duplicates-by-tag`, `--retag-from-path`, `--explain-template`, `--in-place`
+    /// and `--dry-run` run without target directory. Dry run targets are shown relative to a placeholder then
+    fn target_directory(&self) -> &Path {
+        match &self.target_directory {
+            Some(target_directory) => target_directory,
+            None if self.explain_template || self.in_place => Path::new(""),
+            None if self.dry_run => Path::new(DRY_RUN_ROOT),
+            None => panic!("Target directory is required unless undoing or checking"),
+        }
+    }
+
+    fn template(&self) -> Template {
+        if self.by_title_letter { TITLE_LETTER_TEMPLATE.parse().unwrap() } else { self.template.clone() }
+    }
+
+    /// Tags the template is expanded with, the file name segment isn't used with --keep-filename
+    fn template_required_tags(&self) -> Vec<Required> {
+        if self.keep_filename { self.template().directory_required_tags() } else { self.template().required_tags() }
+    }
+
+    fn required_tags(&self) -> Vec<Required> {
+        if !self.require.is_empty() {
+            self.require.clone()
+        } else if self.in_place {
+            vec![Required::Title]
+        } else {
+            self.template_required_tags()
+        }
+    }
+
+    fn to_config(&self) -> Config {
+        Config {
+            target_directory: self.target_directory().to_path_buf(),
+            artist_roots: self.artist_root.clone(),
+            template: self.template(),
+            on_conflict: self.on_conflict,
+            skip_if_newer: self.skip_if_newer,
+            target_exists_ok: self.target_exists_ok.then_some(self.compare_by),
+            dry_run: self.dry_run,
+            interactive: self.interactive,
+            move_files: self.move_files,
+            in_place: self.in_place,
+            hardlink: self.hardlink,
+            symlink: self.symlink,
+            rollback_on_error: self.rollback_on_error,
+            number_tracks: self.number_tracks,
+            track_number_width: self.track_number_width,
+            always_disc: self.always_disc,
+            disc_layout: self.disc_layout,
+            case: self.case,
+            extension_case: self.extension_case,
+            replacement_char: self.replacement_char.clone(),
+            normalize: self.normalize,
+            unicode_form: self.unicode_form,
+            copy_cover: self.copy_cover,
+            write_lrc: self.write_lrc,
+            preserve_mtime: !self.no_preserve_mtime,
+            follow_symlinks: self.follow_symlinks,
+            reflink: self.reflink,
+            verify: self.verify.then_some(if self.delete_bad_copies { BadCopy::Delete } else { BadCopy::Keep }),
+            max_path_length: self.max_path_length,
+            flatten: self.flatten,
+            flatten_separator: self.flatten_separator.clone(),
+            keep_filename: self.keep_filename,
+            singles_folder: self.singles_folder.clone(),
+            genre_top: self.genre_top,
+            genre_separator: self.genre_separator.clone(),
+            letter_buckets: self.letter_buckets,
+            by_title_letter: self.by_title_letter,
+            other_bucket: self.other_bucket.clone(),
+            split_by_format: self.split_by_format,
+            fix_extension: self.fix_extension,
+            artist_source: self.artist_source,
+            allow_partial: self.allow_partial,
+            required: self.required_tags(),
+            max_errors: self.max_errors,
+            tag_version: self.tag_version,
+            comment_key: self.comment_key.clone().unwrap_or_default(),
+            multi_artist: self.multi_artist,
+            multi_artist_separator: self.multi_artist_separator.clone(),
+            strip_featured: self.strip_featured,
+            ignore_articles: self.ignore_articles,
+            articles: self.articles.clone(),
+            use_sort_names: self.use_sort_names,
+            clean_album: self.clean_album,
+            album_mbid: self.album_mbid,
+            audio_suffix: self.audio_suffix,
+            album_patterns: self.clean_album_pattern.clone(),
+            compilations: self.compilations,
+            compilation_folder: self.compilation_folder.clone(),
+            dedup: self.dedup,
+            write_missing_tags: self.write_missing_tags,
+            tag_fallback: self.tag_fallback.clone(),
+            same_ext_only: self.same_ext_only.clone(),
+            tag_fallback_timeout: Duration::from_secs(self.tag_fallback_timeout),
+            require_replaygain: self.require_replaygain,
+            warn_encoding: self.warn_encoding,
+            min_free_space: self.min_free_space,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum OutputFormat {
+    /// Colored status line per file
+    Text,
+    /// JSON array with entry per file, its outcome is one of "ok" (also planned by --dry-run), "skipped" or "error"
+    Json,
+}
+
+/// `--set` key of the extension of the `--explain-template` file
+const EXPLAIN_EXTENSION_KEY: &str = "ext";
+const EXPLAIN_DEFAULT_EXTENSION: &str = "mp3";
+/// Root of targets shown by `--dry-run` without target directory
+const DRY_RUN_ROOT: &str = "<target>";
+/// Status lines of `--stream` are aligned to names of this length
+const STREAM_FILENAME_WIDTH: usize = 30;
+/// Name of the `--explain-template` file kept by `--keep-filename`
+const EXPLAIN_FILE_STEM: &str = "Original Name";
+
+/// Set by `--print-targets`, which reserves stdout for target paths
+static STATUS_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// `println!` for status lines and summary
+macro_rules! print_status {
+    ($($arg:tt)*) => {
+        if STATUS_TO_STDERR.load(Ordering::Relaxed) {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
 }
 
 // TODO: clap - set `files` to be non-empty?
 fn main() {
-    let args = CliArgs::parse();
-    if args.files.len() == 0 {
-        println!("{} You must specify at least one file!", "ERROR!".red().bold());
+    let args = config::parse_args();
+    music_shelf_manager::set_verbosity(args.verbose);
+    if let Some(log_file) = &args.log_file {
+        if let Err(err) = logging::init_log_file(log_file, args.verbose) {
+            eprintln!("{} Cannot open log file '{}': {}", "ERROR!".red().bold(), log_file.display(), err);
+            exit(2);
+        }
+    }
+    STATUS_TO_STDERR.store(args.print_targets, Ordering::Relaxed);
+    colored::control::set_override(use_colors(&args));
+
+    if let Some(manifest_path) = &args.undo {
+        let summary = undo::undo(manifest_path, args.target_directory.as_deref()).unwrap_or_else(|err| {
+            eprintln!("{} Cannot read manifest '{}': {}", "ERROR!".red().bold(), manifest_path.display(), err);
+            exit(2);
+        });
+        print_summary(&summary);
+        if summary.errors > 0 {
+            exit(1);
+        }
+        return;
+    }
+
+    if args.explain_template {
+        explain_template(&args);
+        return;
+    }
+
+    // Streamed files are read later, while they are handled
+    let streamed_inputs = args.stream.then(|| {
+        input::stream_file_lists(&args.files, args.files_from.as_deref(), args.null).unwrap_or_else(|err| {
+            eprintln!("{} Cannot read list of files: {}", "ERROR!".red().bold(), err);
+            exit(2);
+        })
+    });
+    let inputs = if args.stream {
+        Vec::new()
+    } else {
+        input::merge_file_lists(&args.files, args.files_from.as_deref(), args.null).unwrap_or_else(|err| {
+            eprintln!("{} Cannot read list of files: {}", "ERROR!".red().bold(), err);
+            exit(2);
+        })
+    };
+    let has_inputs = if args.stream { !args.files.is_empty() || args.files_from.is_some() } else { !inputs.is_empty() };
+    if !has_inputs && args.watch.is_none() {
+        eprintln!("{} You must specify at least one file!", "ERROR!".red().bold());
         exit(2);
     }
 
-    let file_path_pretty_print: fn(&PathBuf) -> &str = |path| {
+    let file_path_pretty_print: fn(&Path) -> &str = |path| {
         path.file_name().and_then(|fname| { fname.to_str() }).unwrap_or("<N/A>")
     };
 
-    let longest_filename_len = args.files.iter()
-        .map(|fname| { file_path_pretty_print(fname) })
-        .map(|fname| { fname.chars().count() })
-        .max()
-        .unwrap_or(1);
+    // Overwritten files can't be brought back
+    if args.rollback_on_error && args.on_conflict == ConflictAction::Overwrite {
+        eprintln!("{} --rollback-on-error can't be used with --on-conflict overwrite!", "ERROR!".red().bold());
+        exit(2);
+    }
 
+    if args.interactive && !io::stdin().is_terminal() {
+        eprintln!("{} Interactive mode requires stdin to be a terminal!", "ERROR!".red().bold());
+        exit(2);
+    }
 
-    for file in args.files {
-        let file_result = handle_file(&file, &args.target_directory);
+    let extensions = if args.extensions.is_empty() {
+        tags::SUPPORTED_EXTENSIONS.iter().map(|ext| { ext.to_string() }).collect()
+    } else {
+        args.extensions.clone()
+    };
+    let exclude = args.exclude.iter()
+        .fold(GlobSetBuilder::new(), |mut builder, glob| { builder.add(glob.clone()); builder })
+        .build()
+        .unwrap_or_else(|err| {
+            eprintln!("{} Invalid --exclude pattern: {}", "ERROR!".red().bold(), err);
+            exit(2);
+        });
+    let mut files = input::collect_files(&inputs, args.recursive, &extensions, &exclude);
 
-        let filename = file_path_pretty_print(&file);
-        print_handling_status(filename, longest_filename_len, &file_result);
+    let read_options = tags::ReadOptions {
+        artist_source: args.artist_source,
+        singles_album: args.singles_folder.clone(),
+        missing_title: None,
+        allow_partial: args.allow_partial,
+        required: args.required_tags(),
+        tag_version: args.tag_version,
+        comment_key: args.comment_key.clone().unwrap_or_default(),
+    };
+    if args.report_duplicates_by_tag {
+        let (groups, unreadable) = duplicates::find_duplicates(&files, &read_options);
+        duplicates::print_duplicates(&groups, unreadable);
+        return;
+    }
+    if let Some(min_track_count) = args.min_track_count {
+        let (albums, unreadable) = albums::find_incomplete_albums(&files, &read_options, min_track_count);
+        albums::print_incomplete_albums(&albums, unreadable);
+        return;
+    }
+    if args.retag_from_path {
+        let summary = retag::retag_from_path(&files, args.force_retag);
+        retag::print_retag_summary(&summary);
+        if summary.failed > 0 {
+            exit(1);
+        }
+        return;
+    }
+    if args.check {
+        let summary = check::check(&files, &read_options);
+        check::print_check_summary(&summary);
+        if summary.incomplete > 0 || summary.unreadable > 0 {
+            exit(1);
+        }
+        return;
+    }
+
+    // Template isn't used to rename files in place
+    if !args.require.is_empty() && !args.in_place {
+        if let Some(missing) = args.template_required_tags().iter().find(|tag| { !args.require.contains(tag) }) {
+            eprintln!("{} Template uses {}, which is not listed in --require!", "ERROR!".red().bold(), missing.name());
+            exit(2);
+        }
+    }
 
-        if args.remove_source_file && file_result.is_ok() {
-            let removal_result = fs::remove_file(file);
-            if removal_result.is_err() {
-                let err = removal_result.unwrap_err();
-                println!(
-                    "{} Original file wan't removed due to error: {}",
-                    "Warning!".yellow().bold(),
-                    err
-                );
+    // Dry run must not touch the disk
+    if !args.dry_run && !args.in_place {
+        let artist_roots = args.artist_root.iter().map(|(_, root)| { root.as_path() });
+        for root in std::iter::once(args.target_directory()).chain(artist_roots) {
+            if let Err(err) = check_target_directory(root) {
+                eprintln!("{} Target directory '{}' is not usable: {}", "ERROR!".red().bold(), root.display(), err);
+                exit(2);
             }
         }
     }
-}
+
+    // Streamed files are not known in advance, so names aren't aligned to the longest one
+    let longest_filename_len = if args.stream {
+        STREAM_FILENAME_WIDTH
+    } else {
+        files.iter()
+            .map(|fname| { file_path_pretty_print(fname) })
+            .map(|fname| { fname.chars().count() })
+            .max()
+            .unwrap_or(1)
+    };
 
 
-fn handle_file(filepath: &PathBuf, root_folder: &PathBuf) -> Result<(), Vec<id3::Error>> {
-    let tag_result = Tag::read_from_path(filepath);
-    if tag_result.is_err() {
-        return Result::Err(vec![tag_result.err().unwrap()]);
+    let resume = args.resume.as_ref().map(|path| {
+        resume::ResumeFile::open(path).unwrap_or_else(|err| {
+            eprintln!("{} Cannot use state file '{}': {}", "ERROR!".red().bold(), path.display(), err);
+            exit(2);
+        })
+    });
+    if let Some(resume) = &resume {
+        let total = files.len();
+        files.retain(|file| { !resume.is_done(file) });
+        if files.len() < total && args.quiet < 2 {
+            print_status!("Skipping {} files completed by previous runs", total - files.len());
+        }
     }
 
-    let tag = tag_result.unwrap();
-    let required_tags = vec![
-        tag.album_artist().ok_or(Error::new(ErrorKind::NoTag, "No album artist found")),
-        tag.album().ok_or(Error::new(ErrorKind::NoTag, "No album found")),
-        tag.title().ok_or(Error::new(ErrorKind::NoTag, "No title found")),
-    ];
+    let mut manifest = args.manifest.as_ref().map(|path| {
+        manifest::Manifest::create(path).unwrap_or_else(|err| {
+            eprintln!("{} Cannot create manifest '{}': {}", "ERROR!".red().bold(), path.display(), err);
+            exit(2);
+        })
+    });
 
-    handle_tags(required_tags)
-        .map(|tags| {
-            if let [artist, album, title] = &tags[..] {
-                RequiredTags {
-                    album: (*album).clone(),
-                    artist: (*artist).clone(),
-                    title: (*title).clone(),
-                }
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.jobs.map(NonZeroUsize::get).unwrap_or(0))
+        .build()
+        .unwrap_or_else(|err| {
+            eprintln!("{} Cannot start worker threads: {}", "ERROR!".red().bold(), err);
+            exit(2);
+        });
+    let config = args.to_config();
+    let state = RunState::default();
+
+    // Bar would only get in the way of prompts and machine-readable output, and there is no total when watching or streaming
+    if status_is_terminal(&args) && args.format == OutputFormat::Text && !args.interactive && args.watch.is_none() && !args.stream {
+        progress::start(files.len(), args.print_targets);
+    }
+
+    let mut reports = Vec::new();
+    let mut summary = report::Summary::default();
+    let mut deferred_removals = Vec::new();
+    let mut changed_albums = BTreeSet::new();
+    let mut report_results = |file: &Path, file_results: Vec<Result<Outcome, Vec<ManageError>>>| {
+        let all_transferred = !file_results.is_empty() && file_results.iter().all(|file_result| { file_result.as_ref().is_ok_and(Outcome::is_transferred) });
+        let mut source_removed = false;
+        if args.remove_source_file && all_transferred {
+            if args.rollback_on_error {
+                // Source is needed until it's known that the run won't be rolled back
+                deferred_removals.push(file.to_path_buf());
+                source_removed = true;
             } else {
-                panic!("Tags amount does not match expected (3).")
+                source_removed = remove_source(file);
             }
-        })
-        .and_then(|tags| {
-            let target_path = generate_target_path(filepath, root_folder, tags);
-            copy_file(filepath, &target_path).map_err(|e| { vec![e] })
-        })
-}
+        }
+        let last_idx = file_results.len().saturating_sub(1);
+        let source_moved = source_removed || ((args.move_files || args.in_place) && file_results.last().is_some_and(|file_result| {
+            file_result.as_ref().is_ok_and(Outcome::is_transferred)
+        }));
 
+        for (idx, file_result) in file_results.iter().enumerate() {
+            summary.add(file_result);
+            report::log_result(file, file_result);
+            if let (Some(_), Ok(outcome)) = (&args.on_album_complete, file_result) {
+                if let (true, Some(album_dir)) = (outcome.is_transferred(), outcome.target().parent()) {
+                    changed_albums.insert(album_dir.to_path_buf());
+                }
+            }
 
-fn handle_tags(tags: Vec<Result<&str, Error>>) -> Result<Vec<String>, Vec<Error>> {
-    let mut result = Result::Ok(Vec::new());
+            match args.format {
+                OutputFormat::Text if args.quiet > 0 && is_success(file_result) => {},
+                OutputFormat::Text => {
+                    let filename = file_path_pretty_print(file);
+                    progress::suspend(|| { print_handling_status(filename, longest_filename_len, file_result, args.target_exists_ok) });
+                },
+                OutputFormat::Json => reports.push(report::FileReport::new(file, file_result)),
+            }
 
-    for current_tag in tags {
-        match result {
-            Ok(mut tags) => {
-                match current_tag {
-                    Ok(tag_value) => {
-                        tags.push(String::from(tag_value));
-                        result = Ok(tags);
-                    },
-                    Err(tag_error) => {
-                        result = Err(vec![tag_error]);
-                    }
+            if args.print_targets {
+                if let (true, Ok(outcome)) = (is_success(file_result), file_result) {
+                    progress::suspend(|| { println!("{}", outcome.target().display()) });
                 }
-            },
-            Err(mut errors) => {
-                match current_tag {
-                    Ok(_) => {
-                        result = Err(errors);
-                    },
-                    Err(tag_error) => {
-                        errors.push(tag_error);
-                        result = Err(errors);
-                    }
+            }
+
+            // Source ends up at the last target only, the rest are copies
+            let action = match transfer_action(&args) {
+                manifest::Action::Moved if idx == last_idx && source_moved => manifest::Action::Moved,
+                manifest::Action::Moved => manifest::Action::Copied,
+                action => action,
+            };
+            if let Some(manifest) = manifest.as_mut() {
+                let root = file_result.as_ref().ok()
+                    .and_then(|outcome| { outcome.destination() })
+                    .and_then(|destination| { config.root_of(destination) });
+                if let Err(err) = manifest.add(file, file_result, action, root) {
+                    print_warning(&format!("Cannot write manifest entry of '{}': {}", file.display(), err));
                 }
             }
         }
+
+        if args.prune_empty_dirs && source_moved && !args.rollback_on_error {
+            prune_source_dirs(file, &config);
+        }
+
+        // Failed files are tried again by the next run, as well as the ones left for another extension
+        let is_done = !file_results.is_empty() && file_results.iter().all(|result| { matches!(result, Ok(outcome) if !matches!(outcome, Outcome::OtherExtension(_))) });
+        if let (Some(resume), true) = (&resume, is_done) {
+            if let Err(err) = resume.record(file) {
+                print_warning(&format!("Cannot record '{}' in state file: {}", file.display(), err));
+            }
+        }
+
+        progress::inc();
+    };
+
+    if let Some(inbox) = &args.watch {
+        print_status!("Watching '{}', press Ctrl-C to stop", inbox.display());
+        let watch_result = watch::watch(inbox, args.recursive, &extensions, |file| {
+            if resume.as_ref().is_some_and(|resume| { resume.is_done(file) }) {
+                return ControlFlow::Continue(());
+            }
+            let file_results = handle_file(file, &config, &state);
+            report_results(file, file_results);
+            if state.is_error_limit_reached(&config) { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+        });
+        if let Err(err) = watch_result {
+            eprintln!("{} Cannot watch '{}': {}", "ERROR!".red().bold(), inbox.display(), err);
+            exit(2);
+        }
+    } else if let Some(streamed_inputs) = streamed_inputs {
+        thread::scope(|scope| {
+            let (sender, receiver) = mpsc::channel();
+            scope.spawn(|| {
+                pool.install(|| {
+                    streamed_inputs
+                        .flat_map(|input| { input::collect_files(&[input], args.recursive, &extensions, &exclude) })
+                        .filter(|file| { resume.as_ref().is_none_or(|resume| { !resume.is_done(file) }) })
+                        .par_bridge()
+                        .for_each_with(sender, |sender, file| {
+                            let file_results = handle_file(&file, &config, &state);
+                            sender.send((file, file_results)).unwrap();
+                        });
+                });
+            });
+
+            // Results are reported as they come, so that nothing piles up
+            for (file, file_results) in receiver {
+                report_results(&file, file_results);
+            }
+        });
+    } else if args.interactive {
+        // Prompts must not interleave, so files are handled one by one
+        for file in &files {
+            let file_results = handle_file(file, &config, &state);
+            report_results(file, file_results);
+            if state.is_aborted() {
+                break;
+            }
+        }
+    } else {
+        thread::scope(|scope| {
+            let (sender, receiver) = mpsc::channel();
+            scope.spawn(|| {
+                pool.install(|| {
+                    files.par_iter().enumerate().for_each_with(sender, |sender, (idx, file)| {
+                        // Receiver lives until all results are sent
+                        sender.send((idx, handle_file(file, &config, &state))).unwrap();
+                    });
+                });
+            });
+
+            // Results come in arbitrary order, so they are buffered until all previous files are reported
+            let mut pending = BTreeMap::new();
+            let mut next_idx = 0;
+            for (idx, file_results) in receiver {
+                pending.insert(idx, file_results);
+                while let Some(file_results) = pending.remove(&next_idx) {
+                    report_results(&files[next_idx], file_results);
+                    next_idx += 1;
+                }
+            }
+        });
+    }
+
+    progress::finish();
+
+    if state.is_error_limit_reached(&config) {
+        print_status!("{}", format!("Reached --max-errors limit of {} failed files, the rest were not handled", args.max_errors).red().bold());
+    }
+
+    if args.rollback_on_error {
+        if summary.errors > 0 {
+            let (rolled_back, placed) = rollback(&state);
+            print_status!("{}", format!("Rolled back {} of {} placed files", rolled_back, placed).yellow().bold());
+        } else {
+            for file in &deferred_removals {
+                if remove_source(file) && args.prune_empty_dirs {
+                    prune_source_dirs(file, &config);
+                }
+            }
+        }
+        // Moved files are pruned here, as they might have had to be put back
+        if summary.errors == 0 && args.move_files && args.prune_empty_dirs {
+            for source in state.moved_sources() {
+                prune_source_dirs(&source, &config);
+            }
+        }
     }
 
-    result
+    // Rolled back albums didn't change after all
+    let is_rolled_back = args.rollback_on_error && summary.errors > 0;
+    if let (Some(command), false) = (&args.on_album_complete, is_rolled_back) {
+        for album_dir in &changed_albums {
+            if let Err(err) = hook::run_album_hook(command, album_dir) {
+                print_warning(&format!("Hook of album '{}' failed: {}", album_dir.display(), err));
+            }
+        }
+    }
+
+    if let (Some(manifest), Some(path)) = (manifest, &args.manifest) {
+        if let Err(err) = manifest.finish() {
+            eprintln!("{} Cannot write manifest '{}': {}", "ERROR!".red().bold(), path.display(), err);
+            summary.errors += 1;
+        }
+    }
+
+    // Dry run doesn't create anything
+    summary.dirs_created = Some(state.new_dir_count()).filter(|_| { !args.dry_run });
+    if args.format == OutputFormat::Json {
+        report::print_json(&reports, &summary);
+    } else if args.quiet < 2 {
+        print_summary(&summary);
+    }
+
+    // Exit code 2 is reserved for startup errors
+    if summary.errors > 0 {
+        exit(1);
+    }
 }
 
-fn generate_target_path(source: &PathBuf, root_folder: &PathBuf, tags: RequiredTags) -> PathBuf {
-    let mut result_path = PathBuf::new();
-    result_path.push(root_folder);
-    result_path.push(normalize_path_entry(tags.artist.as_str()));
-    result_path.push(normalize_path_entry(tags.album.as_str()));
+/// Extension as matched against files, i.e. lowercase and without leading dot
+fn parse_extension(value: &str) -> Result<String, String> {
+    let extension = value.trim().trim_start_matches('.');
+    if extension.is_empty() {
+        return Err(String::from("extension must not be empty"));
+    }
+    Ok(extension.to_lowercase())
+}
 
-    let mut full_target_filename = normalize_path_entry(tags.title.as_str());
-    let ext = source.extension().map(|ext| { ext.to_str().unwrap() });
-    if ext.is_some() {
-        full_target_filename.push_str(".");
-        full_target_filename.push_str(ext.unwrap());
+/// Replacement must not bring forbidden symbols back
+fn parse_replacement_char(value: &str) -> Result<String, String> {
+    if value.chars().count() > 1 {
+        return Err(String::from("expected single character or empty value"));
+    }
+    if value.chars().any(|c| { FORBIDDEN_SYMBOLS.contains(&c) || c.is_control() }) {
+        return Err(format!("'{}' is forbidden in file names itself", value));
     }
+    Ok(value.to_string())
+}
 
-    result_path.push(full_target_filename);
-    result_path
+fn parse_glob(value: &str) -> Result<Glob, String> {
+    Glob::new(value).map_err(|err| { err.to_string() })
 }
 
-fn normalize_path_entry(path_entry: &str) -> String {
-    // Based on: https://stackoverflow.com/a/31976060
-    let mut result = path_entry.to_string()
-        .replace(FORBIDDEN_SYMBOLS, "_")
-        .replace(Vec::from_iter((0..=31).map(|b| { char::from_u32(b).unwrap()})).as_slice(), "");
+fn parse_artist_root(value: &str) -> Result<(GlobMatcher, PathBuf), String> {
+    let (pattern, directory) = value.split_once('=').ok_or_else(|| { String::from("expected PATTERN=DIRECTORY") })?;
+    if directory.is_empty() {
+        return Err(String::from("directory is empty"));
+    }
+    let matcher = GlobBuilder::new(pattern.trim())
+        .case_insensitive(true)
+        .build()
+        .map_err(|err| { err.to_string() })?
+        .compile_matcher();
+    Ok((matcher, PathBuf::from(directory)))
+}
+
+fn parse_regex(value: &str) -> Result<Regex, String> {
+    Regex::new(value).map_err(|err| { err.to_string() })
+}
 
-    let extension_separator = ".";
-    let split_by_separator = result.splitn(2, extension_separator).collect::<Vec<&str>>();
-    let filename = split_by_separator.first().unwrap();
+/// Whether stream of status lines is shown in terminal
+fn status_is_terminal(args: &CliArgs) -> bool {
+    if args.print_targets { io::stderr().is_terminal() } else { io::stdout().is_terminal() }
+}
 
-    if RESERVED_WINDOWS_NAMES.contains(filename) {
-        result = result.replacen(filename, "_", 1);
+fn use_colors(args: &CliArgs) -> bool {
+    match (args.no_color, args.color) {
+        (true, _) | (false, ColorChoice::Never) => false,
+        (false, ColorChoice::Always) => true,
+        (false, ColorChoice::Auto) => {
+            // https://no-color.org: any non-empty value disables colors
+            let no_color = env::var_os("NO_COLOR").is_some_and(|value| { !value.is_empty() });
+            !no_color && status_is_terminal(args)
+        },
     }
+}
 
-    result
+/// What is done to successfully handled files in the current mode
+fn transfer_action(args: &CliArgs) -> manifest::Action {
+    if args.move_files || args.remove_source_file || args.in_place {
+        manifest::Action::Moved
+    } else if args.hardlink {
+        manifest::Action::Hardlinked
+    } else if args.symlink {
+        manifest::Action::Symlinked
+    } else {
+        manifest::Action::Copied
+    }
 }
 
-fn copy_file(source: &PathBuf, target: &PathBuf) -> Result<(), Error> {
-    target.parent()
-        .ok_or(Error::new(ErrorKind::InvalidInput, format!("Unexpected error while copying file to target '{}'", target.to_str().unwrap())))
-        .and_then(|parent_dir| {
-            fs::create_dir_all(parent_dir).map_err(|io_err| { Error::new(ErrorKind::Io(io_err), format!("Cannot create directory '{}'", parent_dir.to_str().unwrap())) })
-        })
-        .and_then(|()| {
-            fs::copy(source, target)
-                .map(|_| { () })
-                .map_err(|io_err| { Error::new(ErrorKind::Io(io_err), "Failed to copy file") })
-        })
+/// `TAG=VALUE` of `--set`, where the tag is one of `tags::VALUE_KEYS` or the extension
+fn parse_tag_value(value: &str) -> Result<(String, String), String> {
+    let (tag, value) = value.split_once('=').ok_or_else(|| { String::from("expected TAG=VALUE") })?;
+    let tag = tag.trim().to_lowercase();
+    if tag != EXPLAIN_EXTENSION_KEY && !tags::VALUE_KEYS.contains(&tag.as_str()) {
+        return Err(format!("unknown tag '{}', expected one of {}, {}", tag, tags::VALUE_KEYS.join(", "), EXPLAIN_EXTENSION_KEY));
+    }
+    Ok((tag, value.to_string()))
+}
+
+/// Separators become part of the file name, so they have to be valid in it
+fn parse_separator(value: &str) -> Result<String, String> {
+    if value.chars().any(|c| { FORBIDDEN_SYMBOLS.contains(&c) || c.is_control() }) {
+        return Err(format!("'{}' contains symbols forbidden in file names", value));
+    }
+    Ok(value.to_string())
+}
+
+/// Prints targets of the imaginary file of `--explain-template`, exits with 1 if there are none
+fn explain_template(args: &CliArgs) {
+    let config = args.to_config();
+    let extension = args.set.iter().rev()
+        .find(|(tag, _)| { tag == EXPLAIN_EXTENSION_KEY })
+        .map_or(EXPLAIN_DEFAULT_EXTENSION, |(_, extension)| { extension.trim_start_matches('.') });
+    let mut read_options = config.read_options();
+    // Kept file name is the one of the imaginary file
+    read_options.missing_title = args.keep_filename.then(|| { String::from(EXPLAIN_FILE_STEM) });
+
+    let targets = match tags::tags_from_values(&args.set, &read_options) {
+        Ok(tags) => {
+            let stem = if args.keep_filename { EXPLAIN_FILE_STEM } else { tags.title.as_str() };
+            let source = PathBuf::from(format!("{}.{}", stem, extension));
+            music_shelf_manager::explain_targets(&source, tags, &config)
+        },
+        Err(errors) => vec![Err(errors)],
+    };
+    let mut has_errors = false;
+    for target in targets {
+        match target {
+            Ok(target) => println!("{}", target.display()),
+            Err(errors) => {
+                has_errors = true;
+                for err in errors {
+                    eprintln!("{} {}", "ERROR!".red().bold(), format_error(&err));
+                }
+            },
+        }
+    }
+    if has_errors {
+        exit(1);
+    }
+}
+
+fn print_summary(summary: &report::Summary) {
+    let dirs_created = summary.dirs_created
+        .map(|count| { format!(", {} directories created", count) })
+        .unwrap_or_default();
+    log::info!("Finished: {} ok, {} skipped, {} errors{}", summary.ok, summary.skipped, summary.errors, dirs_created);
+    print_status!(
+        "{}, {}, {}{}",
+        format!("{} ok", summary.ok).green().bold(),
+        format!("{} skipped", summary.skipped).yellow().bold(),
+        format!("{} errors", summary.errors).red().bold(),
+        dirs_created
+    );
 }
 
-fn print_handling_status(filename: &str, longest_filename_len: usize, result: &Result<(), Vec<id3::Error>>) {
+fn print_handling_status(filename: &str, longest_filename_len: usize, result: &Result<Outcome, Vec<ManageError>>, is_content_checked: bool) {
     // Even for longest filename need to add '...'
-    let dots_amount = longest_filename_len - filename.chars().count() + 10;
-    let dots: String = std::iter::repeat(".").take(dots_amount).collect();
+    let dots_amount = longest_filename_len.saturating_sub(filename.chars().count()) + 10;
+    let dots = ".".repeat(dots_amount);
 
     match result {
-        Ok(()) => {
-            println!(
+        Ok(Outcome::Transferred(_)) => {
+            print_status!(
                 "{}{}{}",
                 filename,
                 dots,
@@ -195,28 +1136,116 @@ fn print_handling_status(filename: &str, longest_filename_len: usize, result: &R
             );
 
         },
+        Ok(Outcome::Overwritten(_)) => {
+            print_status!(
+                "{}{}{} {}",
+                filename,
+                dots,
+                "Ok".green().bold(),
+                "(overwritten)".yellow()
+            );
+        },
+        Ok(Outcome::Renamed(target)) => {
+            print_status!(
+                "{}{}{} {}",
+                filename,
+                dots,
+                "Ok".green().bold(),
+                format!("(renamed to '{}')", target.file_name().and_then(|fname| { fname.to_str() }).unwrap_or("<N/A>")).yellow()
+            );
+        },
+        Ok(Outcome::Skipped(_)) if is_content_checked => {
+            print_status!(
+                "{}{}{}",
+                filename,
+                dots,
+                "Skipped: different file exists at target".yellow().bold()
+            );
+        },
+        Ok(Outcome::Skipped(_)) => {
+            print_status!(
+                "{}{}{}",
+                filename,
+                dots,
+                "Skipped: target already exists".yellow().bold()
+            );
+        },
+        Ok(Outcome::AlreadyPresent(_)) => {
+            print_status!(
+                "{}{}{}",
+                filename,
+                dots,
+                "Already present".green()
+            );
+        },
+        Ok(Outcome::UpToDate(_)) => {
+            print_status!(
+                "{}{}{}",
+                filename,
+                dots,
+                "Skipped: target is up to date".yellow().bold()
+            );
+        },
+        Ok(Outcome::Declined(_)) => {
+            print_status!(
+                "{}{}{}",
+                filename,
+                dots,
+                "Skipped by user".yellow().bold()
+            );
+        },
+        Ok(Outcome::NoSpace(_)) => {
+            print_status!(
+                "{}{}{}",
+                filename,
+                dots,
+                "Skipped: not enough free space".yellow().bold()
+            );
+        },
+        Ok(Outcome::Duplicate(original)) => {
+            print_status!(
+                "{}{}{} {}",
+                filename,
+                dots,
+                "Skipped: duplicate of".yellow().bold(),
+                format!("'{}'", original.display()).yellow()
+            );
+        },
+        Ok(Outcome::OtherExtension(_)) => {
+            print_status!(
+                "{}{}{}",
+                filename,
+                dots,
+                "Skipped: extension doesn't match --same-ext-only".yellow().bold()
+            );
+        },
+        Ok(Outcome::Planned(target)) => {
+            print_status!(
+                "{}{}{} -> {}",
+                filename,
+                dots,
+                "DRY".cyan().bold(),
+                target.display()
+            );
+        },
         Err(errors) => {
-            let pretty_error_print: fn(&Error) -> String = |err| {
-                format!("{}: {}", err.kind.to_string(), err.description)
-            };
-
             let (fst, other) = errors.split_first().unwrap();
-            println!(
+            print_status!(
                 "{}{}{}",
                 filename,
                 dots,
-                pretty_error_print(fst).red().bold()
+                format_error(fst).red().bold()
             );
             
-            let indent: String = std::iter::repeat(" ").take(filename.chars().count() + dots_amount).collect();
+            let indent = " ".repeat(filename.chars().count() + dots_amount);
             for err in other {
-            println!(
+            print_status!(
                 "{}{}",
                 indent,
-                pretty_error_print(err).red().bold()
+                format_error(err).red().bold()
             );
 
             }
         }
     }
-}
\ No newline at end of file
+}
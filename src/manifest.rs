@@ -0,0 +1,194 @@
+use std::{fs::File, io, path::{Path, PathBuf}};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{checksum, input, ManageError, Outcome};
+
+/// What was done to the file, as recorded in the manifest
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Copied,
+    Moved,
+    Hardlinked,
+    Symlinked,
+    Skipped,
+    Planned,
+}
+
+/// Status of successfully handled files
+pub const STATUS_OK: &str = "ok";
+
+/// Single row of the manifest. Paths are written lossily for reading, paths that aren't valid UTF-8
+/// are also written exactly as hex-encoded bytes in their own columns, so that undo can find them
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    source: PathBuf,
+    destination: Option<PathBuf>,
+    action: Action,
+    /// `ok` or error text
+    status: String,
+    /// SHA-256 of the destination right after it was written, so that later changes can be detected
+    checksum: Option<String>,
+    /// Manifests written before these columns existed don't have them
+    #[serde(default)]
+    source_bytes: Option<String>,
+    #[serde(default)]
+    destination_bytes: Option<String>,
+    /// Target directory or artist root of the destination, undo doesn't remove directories above it
+    #[serde(default)]
+    root: Option<PathBuf>,
+    #[serde(default)]
+    root_bytes: Option<String>,
+}
+
+/// Row of the manifest read back by `--undo`
+pub struct RecordedEntry {
+    pub source: PathBuf,
+    pub destination: Option<PathBuf>,
+    pub action: Action,
+    pub status: String,
+    pub checksum: Option<String>,
+    pub root: Option<PathBuf>,
+}
+
+impl From<Entry> for RecordedEntry {
+    fn from(entry: Entry) -> Self {
+        RecordedEntry {
+            source: entry.source_bytes.as_deref().and_then(decode_path).unwrap_or(entry.source),
+            destination: entry.destination_bytes.as_deref().and_then(decode_path).or(entry.destination),
+            action: entry.action,
+            status: entry.status,
+            checksum: entry.checksum,
+            root: entry.root_bytes.as_deref().and_then(decode_path).or(entry.root),
+        }
+    }
+}
+
+/// CSV file mapping sources to destinations, written with `--manifest`
+pub struct Manifest {
+    writer: csv::Writer<File>,
+}
+
+impl Manifest {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let writer = csv::Writer::from_path(path).map_err(io::Error::from)?;
+        Ok(Manifest { writer })
+    }
+
+    /// `action` is what is done to successfully handled files in the current mode, `root` is the directory
+    /// the destination was put into
+    pub fn add(&mut self, source: &Path, result: &Result<Outcome, Vec<ManageError>>, action: Action, root: Option<&Path>) -> io::Result<()> {
+        let entry = match result {
+            Ok(outcome) => Entry {
+                source: lossy(source),
+                destination: outcome.destination().map(lossy),
+                action: match outcome {
+                    _ if outcome.is_skipped() => Action::Skipped,
+                    Outcome::Planned(_) => Action::Planned,
+                    _ => action,
+                },
+                status: String::from(STATUS_OK),
+                checksum: if outcome.is_transferred() { destination_checksum(outcome.target()) } else { None },
+                source_bytes: encode_path(source),
+                destination_bytes: outcome.destination().and_then(encode_path),
+                root: root.map(lossy),
+                root_bytes: root.and_then(encode_path),
+            },
+            Err(errors) => Entry {
+                source: lossy(source),
+                destination: None,
+                action,
+                status: errors.iter().map(crate::format_error).collect::<Vec<_>>().join("; "),
+                checksum: None,
+                source_bytes: encode_path(source),
+                destination_bytes: None,
+                root: None,
+                root_bytes: None,
+            },
+        };
+        self.writer.serialize(entry).map_err(io::Error::from)
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Reads all entries of previously written manifest
+pub fn read(path: &Path) -> io::Result<Vec<RecordedEntry>> {
+    csv::Reader::from_path(path)
+        .map_err(io::Error::from)?
+        .deserialize::<Entry>()
+        .map(|entry| { entry.map(RecordedEntry::from) })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(io::Error::from)
+}
+
+fn lossy(path: &Path) -> PathBuf {
+    PathBuf::from(path.to_string_lossy().into_owned())
+}
+
+/// Hex-encoded bytes of `path`, only for paths that can't be written as they are
+fn encode_path(path: &Path) -> Option<String> {
+    if path.to_str().is_some() {
+        return None;
+    }
+    Some(input::path_bytes(path).iter().map(|byte| { format!("{:02x}", byte) }).collect())
+}
+
+fn decode_path(hex: &str) -> Option<PathBuf> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    let bytes = (0..hex.len()).step_by(2)
+        .map(|idx| { hex.get(idx..idx + 2).and_then(|byte| { u8::from_str_radix(byte, 16).ok() }) })
+        .collect::<Option<Vec<u8>>>()?;
+    Some(input::path_from_bytes(&bytes))
+}
+
+/// Checksum is optional: without it undo refuses to touch the file, but the rest of manifest is still useful
+fn destination_checksum(destination: &Path) -> Option<String> {
+    checksum::sha256_file(destination)
+        .map_err(|err| { crate::print_warning(&format!("Cannot compute checksum of '{}': {}", destination.display(), err)) })
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_paths_are_read_back_exactly() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let dir = env::temp_dir().join(format!("manifest-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join(OsStr::from_bytes(b"source \xff.mp3"));
+        let destination = dir.join(OsStr::from_bytes(b"Artist \xfe.mp3"));
+        fs::write(&destination, b"audio").unwrap();
+        let manifest_path = dir.join("run.csv");
+
+        let mut manifest = Manifest::create(&manifest_path).unwrap();
+        manifest.add(&source, &Ok(Outcome::Transferred(destination.clone())), Action::Moved, Some(&dir)).unwrap();
+        manifest.finish().unwrap();
+        let entries = read(&manifest_path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].source, source);
+        assert_eq!(entries[0].destination.as_deref(), Some(destination.as_path()));
+        assert!(entries[0].checksum.is_some());
+        assert_eq!(entries[0].root.as_deref(), Some(dir.as_path()));
+    }
+
+    #[test]
+    fn utf8_paths_have_no_bytes_columns() {
+        assert_eq!(encode_path(Path::new("Artist/Album/Title.mp3")), None);
+        assert_eq!(decode_path("6162"), Some(PathBuf::from("ab")));
+        assert_eq!(decode_path("6"), None);
+    }
+}
@@ -0,0 +1,270 @@
+use clap::ValueEnum;
+use unicode_normalization::UnicodeNormalization;
+
+pub const FORBIDDEN_SYMBOLS: [char; 9] = [ '<', '>', ':', '\"', '/', '\\', '|', '?', '*' ];
+/// The only symbols that can't be part of file name on POSIX systems
+const POSIX_FORBIDDEN_SYMBOLS: [char; 2] = [ '/', '\0' ];
+/// Used for path entries that end up empty after normalization
+const EMPTY_ENTRY_PLACEHOLDER: &str = "Unknown";
+/// Replaces reserved names like "CON", regardless of `--replacement-char`
+const RESERVED_NAME_PLACEHOLDER: &str = "_";
+const RESERVED_WINDOWS_NAMES: [&str; 22] = [ "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9" ];
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum NormalizeMode {
+    /// Safe for Windows: replaces symbols like ':' or '?', strips trailing dots, avoids reserved names
+    Windows,
+    /// Replaces only '/' and NUL
+    Posix,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum UnicodeForm {
+    /// Composed, e.g. 'é' is a single character. Used by most systems and tags
+    Nfc,
+    /// Decomposed, e.g. 'é' is 'e' followed by a combining accent. Used by older macOS filesystems
+    Nfd,
+    /// Keep text as it's written in tags
+    None,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum LetterCase {
+    /// all letters are lowercase
+    Lower,
+    /// ALL LETTERS ARE UPPERCASE
+    Upper,
+    /// Each Word Starts With Uppercase Letter
+    Title,
+}
+
+/// Rules of turning tag values into valid path entries
+#[derive(Clone, Debug)]
+pub struct NormalizeOptions {
+    /// Put in place of forbidden symbols, may be empty
+    pub replacement: String,
+    pub mode: NormalizeMode,
+    pub unicode_form: UnicodeForm,
+    /// `None` keeps the case of tags
+    pub case: Option<LetterCase>,
+}
+
+/// Turns `path_entry` into a valid file or directory name. Depends on nothing but its arguments,
+/// so the same entry always becomes the same name
+pub fn normalize_path_entry(path_entry: &str, options: &NormalizeOptions) -> String {
+    // Case goes last, so that it operates on the sanitized name. Reserved names are compared ignoring case
+    change_case(&sanitize(path_entry, options), options.case)
+}
+
+fn sanitize(path_entry: &str, options: &NormalizeOptions) -> String {
+    let path_entry = match options.unicode_form {
+        UnicodeForm::Nfc => path_entry.nfc().collect::<String>(),
+        UnicodeForm::Nfd => path_entry.nfd().collect::<String>(),
+        UnicodeForm::None => path_entry.to_string(),
+    };
+
+    if options.mode == NormalizeMode::Posix {
+        let result = strip_invisible(&path_entry).replace(POSIX_FORBIDDEN_SYMBOLS, &options.replacement)
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" ");
+        // "." and ".." would point to existing directories instead of creating new ones
+        return match result.as_str() {
+            "" | "." | ".." => EMPTY_ENTRY_PLACEHOLDER.to_string(),
+            _ => result,
+        };
+    }
+
+    // Based on: https://stackoverflow.com/a/31976060
+    let mut result = strip_invisible(&path_entry).replace(FORBIDDEN_SYMBOLS, &options.replacement);
+
+    // Windows silently strips trailing dots and spaces, so the created directory wouldn't match the expected name
+    result = result.split_whitespace().collect::<Vec<&str>>().join(" ");
+    result = result.trim_end_matches(|c: char| { c == '.' || c.is_whitespace() }).to_string();
+    if result.is_empty() {
+        return EMPTY_ENTRY_PLACEHOLDER.to_string();
+    }
+
+    let extension_separator = ".";
+    let split_by_separator = result.splitn(2, extension_separator).collect::<Vec<&str>>();
+    let filename = split_by_separator.first().unwrap();
+
+    // Windows names are case-insensitive, "con" and "Con" are reserved as well
+    if RESERVED_WINDOWS_NAMES.iter().any(|name| { name.eq_ignore_ascii_case(filename) }) {
+        result = result.replacen(filename, RESERVED_NAME_PLACEHOLDER, 1);
+    }
+
+    result
+}
+
+/// Removes control characters and invisible formatting ones like zero-width spaces and bidi overrides,
+/// which would make names look the same while being different, or impossible to type
+fn strip_invisible(path_entry: &str) -> String {
+    path_entry.chars().filter(|c| { !c.is_control() && !is_format_char(*c) }).collect()
+}
+
+/// Characters of Unicode "Format" (Cf) category, which can appear in tags
+fn is_format_char(c: char) -> bool {
+    matches!(c,
+        '\u{00AD}' | '\u{061C}' | '\u{180E}'
+        | '\u{200B}'..='\u{200F}'
+        | '\u{202A}'..='\u{202E}'
+        | '\u{2060}'..='\u{2064}'
+        | '\u{2066}'..='\u{206F}'
+        | '\u{FEFF}'
+        | '\u{FFF9}'..='\u{FFFB}'
+    )
+}
+
+fn change_case(path_entry: &str, case: Option<LetterCase>) -> String {
+    match case {
+        None => path_entry.to_string(),
+        Some(LetterCase::Lower) => path_entry.to_lowercase(),
+        Some(LetterCase::Upper) => path_entry.to_uppercase(),
+        Some(LetterCase::Title) => {
+            let mut result = String::with_capacity(path_entry.len());
+            let mut word_start = true;
+            for c in path_entry.chars() {
+                if word_start {
+                    result.extend(c.to_uppercase());
+                } else {
+                    result.extend(c.to_lowercase());
+                }
+                word_start = c.is_whitespace();
+            }
+            result
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn windows() -> NormalizeOptions {
+        NormalizeOptions { replacement: String::new(), mode: NormalizeMode::Windows, unicode_form: UnicodeForm::Nfc, case: None }
+    }
+
+    fn with_case(case: LetterCase) -> NormalizeOptions {
+        NormalizeOptions { case: Some(case), ..windows() }
+    }
+
+    fn posix() -> NormalizeOptions {
+        NormalizeOptions { mode: NormalizeMode::Posix, ..windows() }
+    }
+
+    fn replacing(replacement: &str) -> NormalizeOptions {
+        NormalizeOptions { replacement: replacement.to_string(), ..windows() }
+    }
+
+    fn with_form(unicode_form: UnicodeForm) -> NormalizeOptions {
+        NormalizeOptions { unicode_form, ..windows() }
+    }
+
+    #[test]
+    fn normalizes_by_table() {
+        let rows = [
+            // Forbidden symbols
+            ("AC/DC", windows(), "ACDC"),
+            ("What? <Live>: \"Best\" | *", windows(), "What Live Best"),
+            ("Back\\Slash", windows(), "BackSlash"),
+            // Reserved names
+            ("CON", windows(), "_"),
+            ("nul.mp3", windows(), "_.mp3"),
+            ("COM10", windows(), "COM10"),
+            ("Console", windows(), "Console"),
+            // Control characters
+            ("Song\u{0}\u{7}\tName", windows(), "SongName"),
+            ("Line\nBreak", windows(), "LineBreak"),
+            ("\u{1F}", windows(), "Unknown"),
+            // Replacement
+            ("AC/DC", replacing("_"), "AC_DC"),
+            ("a:b", replacing("-"), "a-b"),
+            ("Why?", replacing("_"), "Why_"),
+            // Posix mode
+            ("AC/DC: Live?", posix(), "ACDC: Live?"),
+            ("AC/DC", NormalizeOptions { replacement: String::from("+"), ..posix() }, "AC+DC"),
+            ("CON", posix(), "CON"),
+            ("Trailing...", posix(), "Trailing..."),
+            ("..", posix(), "Unknown"),
+            ("", posix(), "Unknown"),
+            // Unicode form
+            ("Caf\u{E9}", with_form(UnicodeForm::Nfd), "Cafe\u{301}"),
+            ("Cafe\u{301}", with_form(UnicodeForm::Nfc), "Caf\u{E9}"),
+            ("Cafe\u{301}", with_form(UnicodeForm::None), "Cafe\u{301}"),
+            // Case
+            ("the dark side", with_case(LetterCase::Title), "The Dark Side"),
+            ("ABBA Gold", with_case(LetterCase::Lower), "abba gold"),
+            ("abba gold", with_case(LetterCase::Upper), "ABBA GOLD"),
+            ("aux", with_case(LetterCase::Upper), "_"),
+        ];
+        for (input, options, expected) in rows {
+            assert_eq!(normalize_path_entry(input, &options), expected, "input {:?}", input);
+        }
+    }
+
+    #[test]
+    fn collapses_whitespace_and_trims_trailing_dots() {
+        assert_eq!(normalize_path_entry("  Song . ", &windows()), "Song");
+        assert_eq!(normalize_path_entry("Long \t  Gone   Song", &windows()), "Long Gone Song");
+        assert_eq!(normalize_path_entry("...", &windows()), "Unknown");
+        assert_eq!(normalize_path_entry(" . . ", &windows()), "Unknown");
+    }
+
+    #[test]
+    fn reserved_names_with_trailing_dots() {
+        assert_eq!(normalize_path_entry("CON.", &windows()), "_");
+        assert_eq!(normalize_path_entry("  PRN . . ", &windows()), "_");
+        assert_eq!(normalize_path_entry("AUX.flac.", &windows()), "_.flac");
+    }
+
+    #[test]
+    fn invisible_characters_are_stripped() {
+        let plain = normalize_path_entry("Hidden Track", &windows());
+        for title in ["Hidden\u{200B} Track", "Hid\u{200D}den Track", "\u{FEFF}Hidden Track", "\u{202E}Hidden Track\u{202C}", "Hidden\u{200F} Track"] {
+            assert_eq!(normalize_path_entry(title, &windows()), plain, "title {:?}", title);
+            assert_eq!(normalize_path_entry(title, &posix()), plain, "title {:?}", title);
+        }
+    }
+
+    #[test]
+    fn composed_and_decomposed_forms_match() {
+        let composed = "Beyonc\u{E9}";
+        let decomposed = "Beyonce\u{301}";
+        let nfc = with_form(UnicodeForm::Nfc);
+        assert_eq!(normalize_path_entry(composed, &nfc), normalize_path_entry(decomposed, &nfc));
+        assert_eq!(normalize_path_entry(decomposed, &nfc), composed);
+
+        let nfd = with_form(UnicodeForm::Nfd);
+        assert_eq!(normalize_path_entry(composed, &nfd), decomposed);
+        assert_eq!(normalize_path_entry(decomposed, &nfd), decomposed);
+    }
+
+    #[test]
+    fn reserved_names_ignore_case() {
+        assert_eq!(normalize_path_entry("Con", &windows()), "_");
+        assert_eq!(normalize_path_entry("CON", &with_case(LetterCase::Lower)), "_");
+        assert_eq!(normalize_path_entry("con", &with_case(LetterCase::Title)), "_");
+        assert_eq!(normalize_path_entry("lpt1.txt", &with_case(LetterCase::Upper)), "_.TXT");
+    }
+
+    #[test]
+    fn case_is_changed_after_sanitization() {
+        let options = NormalizeOptions { replacement: String::from(" "), ..with_case(LetterCase::Title) };
+        assert_eq!(normalize_path_entry("rock?roll", &options), "Rock Roll");
+    }
+
+    /// Tags differing only by case end up in one entry. On case-insensitive filesystems the entry may still match
+    /// an existing directory of another case, which is then reused as is
+    #[test]
+    fn different_case_tags_collide() {
+        for case in [LetterCase::Lower, LetterCase::Upper, LetterCase::Title] {
+            assert_eq!(
+                normalize_path_entry("The Beatles", &with_case(case)),
+                normalize_path_entry("THE BEATLES", &with_case(case))
+            );
+        }
+        assert_eq!(normalize_path_entry("THE BEATLES", &with_case(LetterCase::Title)), "The Beatles");
+        assert_ne!(normalize_path_entry("The Beatles", &windows()), normalize_path_entry("THE BEATLES", &windows()));
+    }
+}
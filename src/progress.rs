@@ -0,0 +1,39 @@
+use std::sync::OnceLock;
+
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+/// Global, so that warnings printed deep inside of file handling don't break the bar
+static PROGRESS_BAR: OnceLock<ProgressBar> = OnceLock::new();
+
+/// Shows bar at the bottom of the terminal, counting handled files out of `total`.
+/// Bar is drawn to the same stream as status lines
+pub fn start(total: usize, to_stderr: bool) {
+    let draw_target = if to_stderr { ProgressDrawTarget::stderr() } else { ProgressDrawTarget::stdout() };
+    let bar = ProgressBar::with_draw_target(Some(total as u64), draw_target);
+    bar.set_style(
+        ProgressStyle::with_template("{bar:40} {pos}/{len} [{elapsed_precise}]")
+            .expect("Progress bar template is valid")
+    );
+    // Bar is already initialized only if called twice, keeping the first one is fine then
+    let _ = PROGRESS_BAR.set(bar);
+}
+
+/// Runs `print` with the bar hidden, so that printed lines end up above it
+pub fn suspend<F: FnOnce()>(print: F) {
+    match PROGRESS_BAR.get() {
+        Some(bar) => bar.suspend(print),
+        None => print(),
+    }
+}
+
+pub fn inc() {
+    if let Some(bar) = PROGRESS_BAR.get() {
+        bar.inc(1);
+    }
+}
+
+pub fn finish() {
+    if let Some(bar) = PROGRESS_BAR.get() {
+        bar.finish_and_clear();
+    }
+}
@@ -0,0 +1,76 @@
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::{ManageError, Outcome};
+
+/// Machine-readable result of handling a single file
+#[derive(Serialize)]
+pub struct FileReport {
+    /// Paths that aren't valid UTF-8 are written lossily, JSON strings can't hold them
+    source: String,
+    target: Option<String>,
+    outcome: &'static str,
+    errors: Vec<String>,
+}
+
+impl FileReport {
+    pub fn new(source: &Path, result: &Result<Outcome, Vec<ManageError>>) -> Self {
+        match result {
+            Ok(outcome) => FileReport {
+                source: source.to_string_lossy().into_owned(),
+                target: outcome.destination().map(|target| { target.to_string_lossy().into_owned() }),
+                // Dry run planned files are `ok`, the same as in the summary
+                outcome: if outcome.is_skipped() { "skipped" } else { "ok" },
+                errors: Vec::new(),
+            },
+            Err(errors) => FileReport {
+                source: source.to_string_lossy().into_owned(),
+                target: None,
+                outcome: "error",
+                errors: errors.iter().map(crate::format_error).collect(),
+            },
+        }
+    }
+}
+
+/// Writes the result of handling `source` to the log as a JSON object, the same as in `--format json` report
+pub fn log_result(source: &Path, result: &Result<Outcome, Vec<ManageError>>) {
+    let level = if result.is_ok() { log::Level::Info } else { log::Level::Error };
+    if log::log_enabled!(level) {
+        let report = FileReport::new(source, result);
+        log::log!(level, "{}", serde_json::to_string(&report).expect("Report is always serializable"));
+    }
+}
+
+/// Amount of files per outcome. Dry run planned files are counted as `ok`
+#[derive(Default, Serialize)]
+pub struct Summary {
+    pub ok: usize,
+    pub skipped: usize,
+    pub errors: usize,
+    /// Only known for runs putting files to the target, not for `--undo`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dirs_created: Option<usize>,
+}
+
+impl Summary {
+    pub fn add(&mut self, result: &Result<Outcome, Vec<ManageError>>) {
+        match result {
+            Ok(outcome) if outcome.is_skipped() => self.skipped += 1,
+            Ok(_) => self.ok += 1,
+            Err(_) => self.errors += 1,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    files: &'a [FileReport],
+    summary: &'a Summary,
+}
+
+pub fn print_json(reports: &[FileReport], summary: &Summary) {
+    let report = JsonReport { files: reports, summary };
+    println!("{}", serde_json::to_string_pretty(&report).expect("Report is always serializable"));
+}
@@ -0,0 +1,40 @@
+use std::{collections::HashSet, fs::{self, File, OpenOptions}, io::{self, Write}, path::{self, Path, PathBuf}, sync::Mutex};
+
+use crate::input::{path_bytes, path_from_bytes};
+
+/// Sources completed by previous runs, written with `--resume`. Every completed source is appended right away,
+/// so that an interrupted run loses nothing. Sources are separated by NUL, so that any path is recorded exactly
+pub struct ResumeFile {
+    /// Only sources of previous runs, the current one doesn't see the same source twice
+    done: HashSet<PathBuf>,
+    writer: Mutex<File>,
+}
+
+impl ResumeFile {
+    /// Reads sources recorded before, the file is created if it doesn't exist yet
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let done = match fs::read(path) {
+            Ok(content) => content.split(|byte| { *byte == b'\0' }).filter(|entry| { !entry.is_empty() }).map(path_from_bytes).collect(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => HashSet::new(),
+            Err(err) => return Err(err),
+        };
+        let writer = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(ResumeFile { done, writer: Mutex::new(writer) })
+    }
+
+    pub fn is_done(&self, source: &Path) -> bool {
+        self.done.contains(&key(source))
+    }
+
+    pub fn record(&self, source: &Path) -> io::Result<()> {
+        // Whole entry is written at once, so that a crash doesn't leave half of it
+        let mut entry = path_bytes(&key(source));
+        entry.push(b'\0');
+        self.writer.lock().unwrap().write_all(&entry)
+    }
+}
+
+/// Sources are recorded with absolute paths, so that the next run may be started from another directory
+fn key(source: &Path) -> PathBuf {
+    path::absolute(source).unwrap_or_else(|_| { source.to_path_buf() })
+}
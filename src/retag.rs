@@ -0,0 +1,79 @@
+use std::path::{self, Path, PathBuf};
+
+use colored::Colorize;
+
+use crate::tags::{self, Required};
+
+/// Results of `--retag-from-path` over all files
+#[derive(Default)]
+pub struct RetagSummary {
+    pub retagged: usize,
+    pub unchanged: usize,
+    pub failed: usize,
+}
+
+/// Writes artist, album and title taken from the "Artist/Album/Title" layout of every file path into its tags.
+/// Only empty tags are filled unless `overwrite` is set. Files aren't moved or copied
+pub fn retag_from_path(files: &[PathBuf], overwrite: bool) -> RetagSummary {
+    let longest_filename_len = files.iter()
+        .map(|file| { filename(file).chars().count() })
+        .max()
+        .unwrap_or(1);
+
+    let mut summary = RetagSummary::default();
+    for file in files {
+        let name = filename(file);
+        let dots = ".".repeat(longest_filename_len - name.chars().count() + 10);
+        let result = values_from_path(file)
+            .ok_or_else(|| { String::from("Path doesn't have artist and album directories") })
+            .and_then(|values| { tags::write_required_tags(file, &values, overwrite).map_err(|err| { crate::format_error(&err) }) });
+        match result {
+            Ok(written) if written.is_empty() => {
+                summary.unchanged += 1;
+                println!("{}{}{}", name, dots, "Nothing to write".yellow());
+            },
+            Ok(written) => {
+                summary.retagged += 1;
+                let written: Vec<String> = written.iter()
+                    .map(|(tag, value)| { format!("{} '{}'", tag.name(), value) })
+                    .collect();
+                println!("{}{}{}", name, dots, format!("Wrote {}", written.join(", ")).green());
+            },
+            Err(err) => {
+                summary.failed += 1;
+                println!("{}{}{}", name, dots, err.red());
+            },
+        }
+    }
+
+    summary
+}
+
+pub fn print_retag_summary(summary: &RetagSummary) {
+    println!(
+        "{}, {}, {}",
+        format!("{} retagged", summary.retagged).green().bold(),
+        format!("{} unchanged", summary.unchanged).yellow().bold(),
+        format!("{} failed", summary.failed).red().bold()
+    );
+}
+
+/// Grandparent directory is the artist, parent directory is the album and file stem is the title
+fn values_from_path(file: &Path) -> Option<Vec<(Required, String)>> {
+    // Relative paths like "song.mp3" don't name their directories
+    let file = path::absolute(file).ok()?;
+    let title = file.file_stem()?.to_str()?;
+    let album_dir = file.parent()?;
+    let album = album_dir.file_name()?.to_str()?;
+    let artist = album_dir.parent()?.file_name()?.to_str()?;
+
+    Some(vec![
+        (Required::Artist, artist.to_string()),
+        (Required::Album, album.to_string()),
+        (Required::Title, title.to_string()),
+    ])
+}
+
+fn filename(path: &Path) -> &str {
+    path.file_name().and_then(|fname| { fname.to_str() }).unwrap_or("<N/A>")
+}
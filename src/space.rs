@@ -0,0 +1,71 @@
+use std::{fs, path::Path, sync::Once};
+
+const SIZE_UNITS: [(char, u64); 4] = [ ('K', 1 << 10), ('M', 1 << 20), ('G', 1 << 30), ('T', 1 << 40) ];
+
+/// Free space is queried for every file, but inability to do so is reported only once
+static UNKNOWN_SPACE_WARNING: Once = Once::new();
+
+/// Parses size in bytes, optionally with binary unit suffix: `1048576`, `512K`, `100M`, `2G`
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (number, multiplier) = match value.chars().last().map(|c| { c.to_ascii_uppercase() }) {
+        Some(suffix) if suffix.is_ascii_alphabetic() => {
+            let (_, multiplier) = SIZE_UNITS.iter()
+                .find(|(unit, _)| { *unit == suffix })
+                .ok_or_else(|| { format!("unknown size unit '{}', expected one of K, M, G, T", suffix) })?;
+            (&value[..value.len() - 1], *multiplier)
+        },
+        _ => (value, 1),
+    };
+
+    number.trim().parse::<u64>()
+        .map_err(|_| { format!("invalid size '{}'", value) })?
+        .checked_mul(multiplier)
+        .ok_or_else(|| { format!("size '{}' is too large", value) })
+}
+
+/// Checks that filesystem of `target_directory` has room for `source` plus `margin` bytes.
+/// Returns free space if the file does not fit. When free space is unknown, file is assumed to fit
+pub fn check_free_space(source: &Path, target_directory: &Path, margin: u64) -> Result<(), u64> {
+    let available = match fs2::available_space(target_directory) {
+        Ok(available) => available,
+        Err(err) => {
+            UNKNOWN_SPACE_WARNING.call_once(|| {
+                crate::print_warning(&format!("Cannot determine free space of '{}', not checking it: {}", target_directory.display(), err));
+            });
+            return Ok(());
+        },
+    };
+    let file_size = fs::metadata(source).map(|metadata| { metadata.len() }).unwrap_or(0);
+
+    if file_size.saturating_add(margin) > available {
+        Err(available)
+    } else {
+        Ok(())
+    }
+}
+
+/// Whether moving `source` into `target_directory` is a plain rename, which does not need free space
+#[cfg(unix)]
+pub fn is_same_filesystem(source: &Path, target_directory: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (fs::metadata(source), fs::metadata(target_directory)) {
+        (Ok(source), Ok(target)) => source.dev() == target.dev(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+pub fn is_same_filesystem(_source: &Path, _target_directory: &Path) -> bool {
+    false
+}
+
+/// Human readable size for messages, e.g. "1.5 MiB"
+pub fn format_size(size: u64) -> String {
+    let unit = SIZE_UNITS.iter().rev().find(|(_, multiplier)| { size >= *multiplier });
+    match unit {
+        Some((unit, multiplier)) => format!("{:.1} {}iB", size as f64 / *multiplier as f64, unit),
+        None => format!("{} B", size),
+    }
+}
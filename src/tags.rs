@@ -0,0 +1,960 @@
+use std::{io, path::Path, str::FromStr};
+
+use clap::ValueEnum;
+use id3::TagLike;
+use lofty::{file::{AudioFile, TaggedFileExt}, tag::{Accessor, ItemKey, TagType}};
+
+use crate::error::ManageError;
+
+/// Extensions of files that are picked up when walking directories
+pub const SUPPORTED_EXTENSIONS: [&str; 5] = [ "mp3", "flac", "ogg", "opus", "m4a" ];
+
+/// Album artist marking compilations, which lack the compilation flag
+const VARIOUS_ARTISTS: &str = "Various Artists";
+
+const ALBUM_FIELD: &str = "album";
+const TITLE_FIELD: &str = "title";
+
+/// Separators of several artists stored in one tag value
+const ARTIST_SEPARATORS: [char; 2] = [ '\0', ';' ];
+
+#[derive(Clone, Debug)]
+pub struct RequiredTags {
+    pub artist: String,
+    /// All values of the artist tag, when it stores several artists
+    pub artists: Vec<String>,
+    pub album: String,
+    pub title: String,
+    pub year: Option<i32>,
+    pub track: Option<u32>,
+    /// Tracks of the disc, e.g. 12 of "3/12"
+    pub total_tracks: Option<u32>,
+    pub disc: Option<u32>,
+    pub total_discs: Option<u32>,
+    /// First genre, or all of them joined by `--genre-separator`
+    pub genre: Option<String>,
+    /// All values of the genre tag
+    pub genres: Vec<String>,
+    /// ReplayGain adjustments as written by the analyzer, e.g. "-6.54 dB"
+    pub track_gain: Option<String>,
+    pub album_gain: Option<String>,
+    /// Artist of this very track, even if the album artist is used as `artist`
+    pub track_artist: Option<String>,
+    /// Track is part of a various artists album
+    pub compilation: bool,
+    /// Sort names like "Beatles, The", used for directories with `--use-sort-names`
+    pub artist_sort: Option<String>,
+    pub album_sort: Option<String>,
+    /// Artist as written in tags, when the directory uses the one changed by `--strip-featured` or `--ignore-articles`
+    pub full_artist: Option<String>,
+    /// Album as written in tags, when the directory uses the one cleaned up by `--clean-album`
+    pub full_album: Option<String>,
+    /// MusicBrainz release ID, unique for every edition of the album
+    pub album_id: Option<String>,
+    /// Comment selected by `--comment-key`
+    pub comment: Option<String>,
+    /// Only read when the template or `--audio-suffix` needs it
+    pub audio: AudioProperties,
+}
+
+impl RequiredTags {
+    pub fn has_replaygain(&self) -> bool {
+        self.track_gain.is_some() || self.album_gain.is_some()
+    }
+}
+
+/// Properties of the audio stream rather than tags
+#[derive(Clone, Debug, Default)]
+pub struct AudioProperties {
+    /// In Hz
+    pub sample_rate: Option<u32>,
+    /// Only lossless formats have it
+    pub bit_depth: Option<u8>,
+    pub channels: Option<u8>,
+}
+
+impl AudioProperties {
+    /// Sample rate in kHz, e.g. "44.1" or "96"
+    pub fn sample_rate_khz(&self) -> Option<String> {
+        self.sample_rate.map(|rate| { (f64::from(rate) / 1000.0).to_string() })
+    }
+
+    /// Bit depth and sample rate like "24-96" of lossless files. `None` for lossy ones, which don't have bit depth
+    pub fn suffix(&self) -> Option<String> {
+        Some(format!("{}-{}", self.bit_depth?, self.sample_rate_khz()?))
+    }
+}
+
+/// Tag files may be required to have to be organized, see `--require`
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Required {
+    Artist,
+    Album,
+    Title,
+}
+
+impl Required {
+    pub const ALL: [Required; 3] = [ Required::Artist, Required::Album, Required::Title ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Required::Artist => "artist",
+            Required::Album => "album",
+            Required::Title => "title",
+        }
+    }
+
+    /// Required tag, which absence is reported by `err`.
+    /// `None` for errors of reading the file, including file without any tags
+    pub fn missing_in(err: &ManageError) -> Option<Required> {
+        match err {
+            ManageError::MissingTag { field } if field == ALBUM_FIELD => Some(Required::Album),
+            ManageError::MissingTag { field } if field == TITLE_FIELD => Some(Required::Title),
+            ManageError::MissingTag { field } if is_artist_field(field) => Some(Required::Artist),
+            _ => None,
+        }
+    }
+}
+
+/// Which tag is used as the artist
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ArtistSource {
+    /// Album artist (TPE2 / ALBUMARTIST)
+    AlbumArtist,
+    /// Track artist (TPE1 / ARTIST)
+    Artist,
+    /// Composer (TCOM / COMPOSER)
+    Composer,
+}
+
+/// Which of ID3v2 and ID3v1 tags of MP3 files are used
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum TagVersion {
+    /// ID3v2, with fields it lacks taken from ID3v1
+    PreferV2,
+    /// ID3v1, with fields it lacks taken from ID3v2
+    PreferV1,
+    /// Ignore ID3v1
+    V2Only,
+}
+
+/// Selects one of the comments, which is used as `{comment}`
+#[derive(Clone, Debug, Default)]
+pub struct CommentKey {
+    /// Empty for the plain comment. Names the field itself in Vorbis comments, which have no descriptions
+    pub description: String,
+    /// Three-letter code like "eng", `None` accepts any language
+    pub language: Option<String>,
+}
+
+impl FromStr for CommentKey {
+    type Err = String;
+
+    /// "DESCRIPTION" or "DESCRIPTION:lng"
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let language = value.rsplit_once(':')
+            .filter(|(_, language)| { language.len() == 3 && language.chars().all(|c| { c.is_ascii_alphabetic() }) });
+        let key = match language {
+            Some((description, language)) => CommentKey { description: description.to_string(), language: Some(language.to_lowercase()) },
+            None => CommentKey { description: value.to_string(), language: None },
+        };
+        Ok(key)
+    }
+}
+
+/// Options changing how tags are interpreted
+#[derive(Clone)]
+pub struct ReadOptions {
+    /// `None` means album artist with fallback to artist
+    pub artist_source: Option<ArtistSource>,
+    /// Album used for files without album tag, `None` means such files are rejected
+    pub singles_album: Option<String>,
+    /// Title used for files without title tag, `None` means such files are rejected
+    pub missing_title: Option<String>,
+    /// Use tags parsed before a corrupt ID3 frame instead of rejecting the file
+    pub allow_partial: bool,
+    pub tag_version: TagVersion,
+    pub comment_key: CommentKey,
+    /// Files missing any of these tags are rejected, other missing ones are left empty
+    pub required: Vec<Required>,
+}
+
+/// Tags that are not required for every file
+struct OptionalTags {
+    artists: Vec<String>,
+    year: Option<i32>,
+    track: Option<u32>,
+    total_tracks: Option<u32>,
+    disc: Option<u32>,
+    total_discs: Option<u32>,
+    genres: Vec<String>,
+    track_gain: Option<String>,
+    album_gain: Option<String>,
+    track_artist: Option<String>,
+    compilation: bool,
+    artist_sort: Option<String>,
+    album_sort: Option<String>,
+    album_id: Option<String>,
+    comment: Option<String>,
+}
+
+/// Picture embedded into the audio file
+pub struct Cover {
+    pub mime_type: String,
+    pub data: Vec<u8>,
+}
+
+impl Cover {
+    /// File extension matching picture's MIME type
+    pub fn extension(&self) -> &str {
+        match self.mime_type.to_lowercase().as_str() {
+            "image/png" => "png",
+            "image/gif" => "gif",
+            "image/bmp" => "bmp",
+            "image/webp" => "webp",
+            _ => "jpg",
+        }
+    }
+}
+
+/// Reads tags using the reader that matches file extension.
+/// Ogg and MP4 containers are read with `lofty`, everything else except FLAC is treated as ID3-tagged file
+pub fn read_tags(filepath: &Path, options: &ReadOptions) -> Result<RequiredTags, Vec<ManageError>> {
+    match file_format(filepath).as_deref() {
+        Some("flac") => read_flac_tags(filepath, options),
+        Some("ogg" | "opus") => read_lofty_tags(filepath, options, ["ALBUMARTIST", "ARTIST", "COMPOSER"]),
+        Some("m4a") => read_lofty_tags(filepath, options, ["aART", "\u{a9}ART", "\u{a9}wrt"]),
+        Some("aac") => Err(vec![ManageError::Unsupported { message: String::from("Raw AAC (ADTS) files are not supported, only AAC in M4A container") }]),
+        _ => read_id3_tags(filepath, options),
+    }
+}
+
+/// Names of tags `tags_from_values` accepts
+pub const VALUE_KEYS: [&str; 17] = [
+    "artist", "album", "title", "year", "track", "total_tracks", "disc", "total_discs", "genre", "artist_sort", "album_sort", "album_id", "compilation",
+    "sample_rate", "bit_depth", "channels", "comment",
+];
+
+/// Tags made of `values`, pairs of one of `VALUE_KEYS` and its value, as if they were read from a file.
+/// Used to try templates out without real files. Separate artists are split the same way as in tags
+pub fn tags_from_values(values: &[(String, String)], options: &ReadOptions) -> Result<RequiredTags, Vec<ManageError>> {
+    // Later values win, like later flags do
+    let value = |key: &str| { values.iter().rev().find(|(name, _)| { name == key }).map(|(_, value)| { value.as_str() }) };
+    let mut errors = Vec::new();
+    let mut number = |key: &str| -> Option<u32> {
+        let number = value(key)?;
+        number.trim().parse()
+            .inspect_err(|_| { errors.push(ManageError::ReadFailed { message: format!("'{}' is not a valid {}", number, key.replace('_', " ")) }) })
+            .ok()
+    };
+
+    let optional_tags = OptionalTags {
+        artists: value("artist").map(|artist| { split_artists([artist]) }).unwrap_or_default(),
+        year: number("year").and_then(|year| { i32::try_from(year).ok() }),
+        track: number("track"),
+        total_tracks: number("total_tracks"),
+        disc: number("disc"),
+        total_discs: number("total_discs"),
+        // ID3v1 genre references like "(17)" are resolved the same as in MP3 files
+        genres: value("genre").map(|genre| { split_artists([genre]).iter().map(|genre| { resolve_genre(genre) }).collect() }).unwrap_or_default(),
+        track_gain: None,
+        album_gain: None,
+        track_artist: value("artist").map(String::from),
+        compilation: is_compilation(value("compilation"), value("artist")),
+        artist_sort: non_empty(value("artist_sort")),
+        album_sort: non_empty(value("album_sort")),
+        album_id: non_empty(value("album_id")),
+        comment: non_empty(value("comment")),
+    };
+    let required_tags = [
+        value("artist").ok_or(ManageError::missing_tag("artist")),
+        value("album").or(options.singles_album.as_deref()).ok_or(ManageError::missing_tag(ALBUM_FIELD)),
+        value("title").or(options.missing_title.as_deref()).ok_or(ManageError::missing_tag(TITLE_FIELD)),
+    ];
+
+    // Sample rate is given in Hz, the same as in files
+    let audio = AudioProperties {
+        sample_rate: number("sample_rate"),
+        bit_depth: number("bit_depth").and_then(|depth| { u8::try_from(depth).ok() }),
+        channels: number("channels").and_then(|channels| { u8::try_from(channels).ok() }),
+    };
+
+    match to_required_tags(required_tags, optional_tags, &options.required) {
+        Ok(tags) if errors.is_empty() => Ok(RequiredTags { audio, ..tags }),
+        Ok(_) => Err(errors),
+        Err(missing) => Err(missing.into_iter().chain(errors).collect()),
+    }
+}
+
+/// Uppercase name of the file format for `--split-by-format`, e.g. "FLAC", or "UNKNOWN" for unrecognized files without extension
+pub fn format_label(filepath: &Path) -> String {
+    file_format(filepath)
+        .map(|format| { format.trim().to_uppercase() })
+        .filter(|label| { !label.is_empty() })
+        .unwrap_or_else(|| { String::from("UNKNOWN") })
+}
+
+/// Format of the file recognized by its content, as one of `SUPPORTED_EXTENSIONS` or "aac" for raw AAC streams.
+/// `None` if the content is not recognized or can't be read
+pub fn detect_format(filepath: &Path) -> Option<&'static str> {
+    let kind = infer::get_from_path(filepath).ok()??;
+    match kind.mime_type() {
+        "audio/mpeg" => Some("mp3"),
+        "audio/x-flac" => Some("flac"),
+        "audio/ogg" => Some("ogg"),
+        "audio/opus" => Some("opus"),
+        "audio/m4a" | "video/mp4" => Some("m4a"),
+        // ADTS, tags can't be read from it
+        "audio/aac" | "audio/x-aac" => Some("aac"),
+        _ => None,
+    }
+}
+
+/// Whether `extension` is usual for the `detected` format. Opus files are often named '.ogg' as well
+pub fn matches_extension(detected: &str, extension: Option<&str>) -> bool {
+    let extension = extension.map(|ext| { ext.to_lowercase() }).unwrap_or_default();
+    extension == detected || (detected == "opus" && extension == "ogg")
+}
+
+/// Lowercase format used to pick the tag reader: detected from the content, falling back to the extension
+fn file_format(filepath: &Path) -> Option<String> {
+    detect_format(filepath)
+        .map(String::from)
+        .or_else(|| {
+            filepath.extension()
+                .and_then(|ext| { ext.to_str() })
+                .map(|ext| { ext.to_lowercase() })
+        })
+}
+
+/// Reads front cover, or any other embedded picture if there is no front cover.
+/// Files, which tags can't be read, are treated as files without pictures
+pub fn read_cover(filepath: &Path) -> Option<Cover> {
+    match file_format(filepath).as_deref() {
+        Some("flac") => {
+            let tag = metaflac::Tag::read_from_path(filepath).ok()?;
+            let mut pictures: Vec<_> = tag.pictures().collect();
+            pictures.sort_by_key(|picture| { picture.picture_type != metaflac::block::PictureType::CoverFront });
+            pictures.first().map(|picture| { Cover { mime_type: picture.mime_type.clone(), data: picture.data.clone() } })
+        },
+        Some("ogg" | "opus" | "m4a") => {
+            let tagged_file = read_lofty_file(filepath).ok()?;
+            let tag = tagged_file.primary_tag().or_else(|| { tagged_file.first_tag() })?;
+            let mut pictures: Vec<_> = tag.pictures().iter().collect();
+            pictures.sort_by_key(|picture| { picture.pic_type() != lofty::picture::PictureType::CoverFront });
+            pictures.first().map(|picture| {
+                Cover {
+                    mime_type: picture.mime_type().map(|mime_type| { mime_type.as_str() }).unwrap_or("image/jpeg").to_string(),
+                    data: picture.data().to_vec(),
+                }
+            })
+        },
+        _ => {
+            let tag = id3::Tag::read_from_path(filepath).ok()?;
+            let mut pictures: Vec<_> = tag.pictures().collect();
+            pictures.sort_by_key(|picture| { picture.picture_type != id3::frame::PictureType::CoverFront });
+            pictures.first().map(|picture| { Cover { mime_type: picture.mime_type.clone(), data: picture.data.clone() } })
+        },
+    }
+}
+
+/// Lyrics in LRC format: synced lyrics get `[mm:ss.xx]` timestamps, unsynced ones are kept as plain text.
+/// Synced lyrics are preferred, of several frames of the same kind the first one is used
+pub fn read_lyrics(filepath: &Path) -> Option<String> {
+    let lyrics = match file_format(filepath).as_deref() {
+        Some("flac") => {
+            let tag = metaflac::Tag::read_from_path(filepath).ok()?;
+            let first_value = |key: &str| { tag.get_vorbis(key).and_then(|mut values| { values.next() }).map(String::from) };
+            first_value("LYRICS").or_else(|| { first_value("UNSYNCEDLYRICS") })
+        },
+        Some("ogg" | "opus" | "m4a") => {
+            let tagged_file = read_lofty_file(filepath).ok()?;
+            let tag = tagged_file.primary_tag().or_else(|| { tagged_file.first_tag() })?;
+            tag.get_string(ItemKey::Lyrics).map(String::from)
+        },
+        _ => {
+            let tag = id3::Tag::read_from_path(filepath).ok()?;
+            // MPEG frame timestamps can't be converted to time without decoding the stream
+            let synced = tag.synchronised_lyrics()
+                .find(|lyrics| { lyrics.timestamp_format == id3::frame::TimestampFormat::Ms })
+                .map(|lyrics| { to_lrc(&lyrics.content) });
+            synced.or_else(|| { tag.lyrics().next().map(|lyrics| { lyrics.text.clone() }) })
+        },
+    };
+    lyrics.filter(|lyrics| { !lyrics.trim().is_empty() })
+}
+
+/// `content` is pairs of milliseconds and text
+fn to_lrc(content: &[(u32, String)]) -> String {
+    content.iter()
+        .map(|(ms, text)| { format!("[{:02}:{:02}.{:02}]{}", ms / 60_000, ms / 1000 % 60, ms / 10 % 100, text.trim_end()) })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Fills in album artist from artist, if file has only the latter. Existing values are never overwritten.
+/// Returns the written value. Only ID3 and FLAC files are supported
+pub fn write_missing_album_artist(filepath: &Path) -> Result<Option<String>, ManageError> {
+    match file_format(filepath).as_deref() {
+        Some("flac") => {
+            let mut tag = metaflac::Tag::read_from_path(filepath).map_err(flac_error)?;
+            let has_album_artist = tag.get_vorbis("ALBUMARTIST").is_some_and(|mut values| { values.next().is_some() });
+            let artist = tag.get_vorbis("ARTIST").and_then(|mut values| { values.next() }).map(String::from);
+            match (has_album_artist, artist) {
+                (false, Some(artist)) => {
+                    tag.set_vorbis("ALBUMARTIST", vec![artist.clone()]);
+                    tag.save().map_err(flac_write_error)?;
+                    Ok(Some(artist))
+                },
+                _ => Ok(None),
+            }
+        },
+        Some("ogg" | "opus" | "m4a") => Err(ManageError::Unsupported { message: String::from("Writing tags of this format is not supported") }),
+        _ => {
+            let mut tag = id3::Tag::read_from_path(filepath)?;
+            match (tag.album_artist(), tag.artist().map(String::from)) {
+                (None, Some(artist)) => {
+                    tag.set_album_artist(artist.clone());
+                    tag.write_to_path(filepath, tag.version()).map_err(id3_write_error)?;
+                    Ok(Some(artist))
+                },
+                _ => Ok(None),
+            }
+        },
+    }
+}
+
+/// Writes artist, album and title from `values`. Only empty ones are written unless `overwrite` is set.
+/// Values that are already there aren't written again. Returns the written values. Only ID3 and FLAC files are supported
+pub fn write_required_tags(filepath: &Path, values: &[(Required, String)], overwrite: bool) -> Result<Vec<(Required, String)>, ManageError> {
+    let vorbis_key = |tag: Required| {
+        match tag {
+            Required::Artist => "ARTIST",
+            Required::Album => "ALBUM",
+            Required::Title => "TITLE",
+        }
+    };
+
+    match file_format(filepath).as_deref() {
+        Some("flac") => {
+            let mut tag = metaflac::Tag::read_from_path(filepath).map_err(flac_error)?;
+            let written: Vec<(Required, String)> = values.iter()
+                .filter(|(required, value)| {
+                    let current: Vec<&str> = tag.get_vorbis(vorbis_key(*required)).map(|values| { values.collect() }).unwrap_or_default();
+                    let has_value = current.iter().any(|current| { !current.trim().is_empty() });
+                    current != [value.as_str()] && (overwrite || !has_value)
+                })
+                .cloned()
+                .collect();
+            if !written.is_empty() {
+                for (required, value) in &written {
+                    tag.set_vorbis(vorbis_key(*required), vec![value.clone()]);
+                }
+                tag.save().map_err(flac_write_error)?;
+            }
+            Ok(written)
+        },
+        Some("ogg" | "opus" | "m4a") => Err(ManageError::Unsupported { message: String::from("Writing tags of this format is not supported") }),
+        _ => {
+            let mut tag = match id3::Tag::read_from_path(filepath) {
+                Ok(tag) => tag,
+                Err(id3::Error { kind: id3::ErrorKind::NoTag, .. }) => id3::Tag::with_version(id3::Version::Id3v24),
+                Err(err) => return Err(ManageError::from(err)),
+            };
+            let written: Vec<(Required, String)> = values.iter()
+                .filter(|(required, value)| {
+                    let current = match required {
+                        Required::Artist => tag.artist(),
+                        Required::Album => tag.album(),
+                        Required::Title => tag.title(),
+                    };
+                    current != Some(value.as_str()) && (overwrite || current.is_none_or(|current| { current.trim().is_empty() }))
+                })
+                .cloned()
+                .collect();
+            if !written.is_empty() {
+                for (required, value) in &written {
+                    match required {
+                        Required::Artist => tag.set_artist(value.clone()),
+                        Required::Album => tag.set_album(value.clone()),
+                        Required::Title => tag.set_title(value.clone()),
+                    }
+                }
+                tag.write_to_path(filepath, tag.version()).map_err(id3_write_error)?;
+            }
+            Ok(written)
+        },
+    }
+}
+
+/// Reads ID3v2 and ID3v1 tags, combined per field in the order of `options.tag_version`
+fn read_id3_tag(filepath: &Path, options: &ReadOptions) -> Result<id3::Tag, ManageError> {
+    let v2_tag = match id3::Tag::read_from_path(filepath) {
+        Ok(tag) => Some(tag),
+        Err(id3::Error { kind: id3::ErrorKind::NoTag, .. }) if options.tag_version != TagVersion::V2Only => None,
+        Err(id3::Error { partial_tag: Some(tag), kind, description }) if options.allow_partial => {
+            let reason = if description.is_empty() { kind.to_string() } else { description };
+            crate::print_warning(&format!("Tags of '{}' are partially corrupt ({}), using readable ones", filepath.display(), reason));
+            Some(tag)
+        },
+        Err(err) => return Err(ManageError::from(err)),
+    };
+    // Trailing ID3v1 tag doesn't have to exist, and its absence is not an error
+    let v1_tag = match options.tag_version {
+        TagVersion::V2Only => None,
+        _ => id3::v1::Tag::read_from_path(filepath).ok().map(id3::Tag::from),
+    };
+
+    let v1_frame_ids = |tag: &id3::Tag| -> Vec<String> { tag.frames().map(|frame| { frame.id().to_string() }).collect() };
+    let (tag, v1_frames) = match (v2_tag, v1_tag) {
+        (Some(v2_tag), Some(v1_tag)) if options.tag_version == TagVersion::PreferV1 => {
+            let v1_frames = v1_frame_ids(&v1_tag);
+            (merge_id3_tags(v1_tag, &v2_tag).0, v1_frames)
+        },
+        (Some(v2_tag), Some(v1_tag)) => merge_id3_tags(v2_tag, &v1_tag),
+        (Some(v2_tag), None) => (v2_tag, Vec::new()),
+        (None, Some(v1_tag)) => {
+            let v1_frames = v1_frame_ids(&v1_tag);
+            (v1_tag, v1_frames)
+        },
+        (None, None) => return Err(ManageError::NoTags),
+    };
+
+    if !v1_frames.is_empty() {
+        crate::print_verbose(1, filepath, &format!("Frames taken from ID3v1 tag: {}", v1_frames.join(", ")));
+    }
+    Ok(tag)
+}
+
+/// Adds frames of `fallback` that `preferred` doesn't have. Returns the merged tag and IDs of the added frames
+fn merge_id3_tags(mut preferred: id3::Tag, fallback: &id3::Tag) -> (id3::Tag, Vec<String>) {
+    // Year of ID3v1 and recording date of ID3v2.4 are the same field
+    let has_date = preferred.date_recorded().is_some() || preferred.year().is_some();
+    let mut added = Vec::new();
+    for frame in fallback.frames() {
+        let is_date = matches!(frame.id(), "TYER" | "TDRC");
+        if preferred.get(frame.id()).is_none() && !(is_date && has_date) {
+            added.push(frame.id().to_string());
+            preferred.add_frame(frame.clone());
+        }
+    }
+    (preferred, added)
+}
+
+fn read_id3_tags(filepath: &Path, options: &ReadOptions) -> Result<RequiredTags, Vec<ManageError>> {
+    let tag = read_id3_tag(filepath, options).map_err(|err| { vec![err] })?;
+    id3_tags(&tag, options)
+}
+
+fn id3_tags(tag: &id3::Tag, options: &ReadOptions) -> Result<RequiredTags, Vec<ManageError>> {
+    let artist = match options.artist_source {
+        None => tag.album_artist().or_else(|| { tag.artist() }),
+        Some(ArtistSource::AlbumArtist) => tag.album_artist(),
+        Some(ArtistSource::Artist) => tag.artist(),
+        Some(ArtistSource::Composer) => tag.text_for_frame_id("TCOM"),
+    };
+
+    let required_tags = [
+        artist.ok_or_else(|| { missing_artist_error(options.artist_source, "TPE2", "TPE1", "TCOM") }),
+        tag.album().or(options.singles_album.as_deref()).ok_or(ManageError::missing_tag(ALBUM_FIELD)),
+        tag.title().or(options.missing_title.as_deref()).ok_or(ManageError::missing_tag(TITLE_FIELD)),
+    ];
+
+    let optional_tags = OptionalTags {
+        // ID3v2.4 separates multiple values with NUL
+        artists: artist.map(|artist| { split_artists([artist]) }).unwrap_or_default(),
+        // ID3v2.4 replaced the year frame (TYER) with the recording date (TDRC)
+        year: tag.year().or_else(|| { tag.date_recorded().map(|date| { date.year }) }),
+        track: tag.track(),
+        total_tracks: tag.total_tracks(),
+        disc: tag.disc(),
+        total_discs: tag.total_discs(),
+        // ID3v2.4 separates multiple values with NUL, each of them may be ID3v1 genre reference like "(17)"
+        genres: split_artists(tag.genres().unwrap_or_default()).iter().map(|genre| { resolve_genre(genre) }).collect(),
+        // ReplayGain has no dedicated frame, so analyzers put it into user-defined text frames
+        track_gain: extended_text(tag, "REPLAYGAIN_TRACK_GAIN"),
+        album_gain: extended_text(tag, "REPLAYGAIN_ALBUM_GAIN"),
+        track_artist: tag.artist().map(String::from),
+        // iTunes compilation flag, not part of the ID3 standard
+        compilation: is_compilation(tag.text_for_frame_id("TCMP"), tag.album_artist()),
+        artist_sort: sort_name_of(artist, [
+            (tag.album_artist(), tag.text_for_frame_id("TSO2")),
+            (tag.artist(), tag.text_for_frame_id("TSOP")),
+            (tag.text_for_frame_id("TCOM"), tag.text_for_frame_id("TSOC")),
+        ]),
+        album_sort: non_empty(tag.text_for_frame_id("TSOA")),
+        // Written by MusicBrainz Picard as a user-defined text frame
+        album_id: non_empty(extended_text(tag, "MusicBrainz Album Id").as_deref()),
+        comment: non_empty(comment(tag, &options.comment_key)),
+    };
+
+    to_required_tags(required_tags, optional_tags, &options.required)
+}
+
+fn read_flac_tags(filepath: &Path, options: &ReadOptions) -> Result<RequiredTags, Vec<ManageError>> {
+    let tag = metaflac::Tag::read_from_path(filepath).map_err(|err| { vec![flac_error(err)] })?;
+
+    let first_value = |key: &str| { tag.get_vorbis(key).and_then(|mut values| { values.next() }) };
+    let has_value = |key: &str| { first_value(key).is_some() };
+    let leading_number = |value: &str| {
+        let digits: String = value.trim().chars().take_while(|c| { c.is_ascii_digit() }).collect();
+        digits.parse().ok()
+    };
+
+    let artist_key = match options.artist_source {
+        None if has_value("ALBUMARTIST") => "ALBUMARTIST",
+        None | Some(ArtistSource::Artist) => "ARTIST",
+        Some(ArtistSource::AlbumArtist) => "ALBUMARTIST",
+        Some(ArtistSource::Composer) => "COMPOSER",
+    };
+    let artist = first_value(artist_key);
+
+    let required_tags = [
+        artist.ok_or_else(|| { missing_artist_error(options.artist_source, "ALBUMARTIST", "ARTIST", "COMPOSER") }),
+        first_value("ALBUM").or(options.singles_album.as_deref()).ok_or(ManageError::missing_tag(ALBUM_FIELD)),
+        first_value("TITLE").or(options.missing_title.as_deref()).ok_or(ManageError::missing_tag(TITLE_FIELD)),
+    ];
+
+    let optional_tags = OptionalTags {
+        // Vorbis comments store each artist as separate value of the same key
+        artists: tag.get_vorbis(artist_key).map(split_artists).unwrap_or_default(),
+        year: first_value("DATE").and_then(leading_number).and_then(|year: u32| { i32::try_from(year).ok() }),
+        track: first_value("TRACKNUMBER").and_then(leading_number),
+        // Total tracks are stored either separately or as "3/12", the same as total discs
+        total_tracks: first_value("TRACKTOTAL").or_else(|| { first_value("TOTALTRACKS") })
+            .or_else(|| { first_value("TRACKNUMBER").and_then(|track| { track.split_once('/').map(|(_, total)| { total }) }) })
+            .and_then(leading_number),
+        disc: first_value("DISCNUMBER").and_then(leading_number),
+        // Total discs are stored either separately or as "1/2"
+        total_discs: first_value("DISCTOTAL").or_else(|| { first_value("TOTALDISCS") })
+            .or_else(|| { first_value("DISCNUMBER").and_then(|disc| { disc.split_once('/').map(|(_, total)| { total }) }) })
+            .and_then(leading_number),
+        genres: tag.get_vorbis("GENRE").map(split_artists).unwrap_or_default(),
+        track_gain: first_value("REPLAYGAIN_TRACK_GAIN").map(String::from),
+        album_gain: first_value("REPLAYGAIN_ALBUM_GAIN").map(String::from),
+        track_artist: first_value("ARTIST").map(String::from),
+        compilation: is_compilation(first_value("COMPILATION"), first_value("ALBUMARTIST")),
+        artist_sort: sort_name_of(artist, [
+            (first_value("ALBUMARTIST"), first_value("ALBUMARTISTSORT")),
+            (first_value("ARTIST"), first_value("ARTISTSORT")),
+            (first_value("COMPOSER"), first_value("COMPOSERSORT")),
+        ]),
+        album_sort: non_empty(first_value("ALBUMSORT")),
+        album_id: non_empty(first_value("MUSICBRAINZ_ALBUMID")),
+        comment: non_empty(first_value(if options.comment_key.description.is_empty() { "COMMENT" } else { &options.comment_key.description })),
+    };
+
+    to_required_tags(required_tags, optional_tags, &options.required)
+}
+
+/// Reads tags of any container supported by `lofty`. `artist_frames` are container's names of
+/// album artist, artist and composer fields, used in error messages.
+/// File without tags is reported the same way as file with empty tags
+fn read_lofty_tags(filepath: &Path, options: &ReadOptions, artist_frames: [&str; 3]) -> Result<RequiredTags, Vec<ManageError>> {
+    let tagged_file = read_lofty_file(filepath).map_err(|err| { vec![lofty_error(err)] })?;
+    let empty_tag = lofty::tag::Tag::new(tagged_file.primary_tag_type());
+    let tag = tagged_file.primary_tag()
+        .or_else(|| { tagged_file.first_tag() })
+        .unwrap_or(&empty_tag);
+
+    let artist_key = match options.artist_source {
+        None if tag.get_string(ItemKey::AlbumArtist).is_some() => ItemKey::AlbumArtist,
+        None | Some(ArtistSource::Artist) => ItemKey::TrackArtist,
+        Some(ArtistSource::AlbumArtist) => ItemKey::AlbumArtist,
+        Some(ArtistSource::Composer) => ItemKey::Composer,
+    };
+    let artist = tag.get_string(artist_key);
+
+    let required_tags = [
+        artist.ok_or_else(|| {
+            let [album_artist_frame, artist_frame, composer_frame] = artist_frames;
+            missing_artist_error(options.artist_source, album_artist_frame, artist_frame, composer_frame)
+        }),
+        tag.get_string(ItemKey::AlbumTitle).or(options.singles_album.as_deref()).ok_or(ManageError::missing_tag(ALBUM_FIELD)),
+        tag.get_string(ItemKey::TrackTitle).or(options.missing_title.as_deref()).ok_or(ManageError::missing_tag(TITLE_FIELD)),
+    ];
+
+    let optional_tags = OptionalTags {
+        artists: split_artists(tag.get_strings(artist_key)),
+        year: tag.date().map(|date| { i32::from(date.year) }),
+        track: tag.track(),
+        total_tracks: tag.track_total(),
+        disc: tag.disk(),
+        total_discs: tag.disk_total(),
+        genres: split_artists(tag.get_strings(ItemKey::Genre)),
+        track_gain: tag.get_string(ItemKey::ReplayGainTrackGain).map(String::from),
+        album_gain: tag.get_string(ItemKey::ReplayGainAlbumGain).map(String::from),
+        track_artist: tag.get_string(ItemKey::TrackArtist).map(String::from),
+        compilation: is_compilation(tag.get_string(ItemKey::FlagCompilation), tag.get_string(ItemKey::AlbumArtist)),
+        artist_sort: sort_name_of(artist, [
+            (tag.get_string(ItemKey::AlbumArtist), tag.get_string(ItemKey::AlbumArtistSortOrder)),
+            (tag.get_string(ItemKey::TrackArtist), tag.get_string(ItemKey::TrackArtistSortOrder)),
+            (tag.get_string(ItemKey::Composer), tag.get_string(ItemKey::ComposerSortOrder)),
+        ]),
+        album_sort: non_empty(tag.get_string(ItemKey::AlbumTitleSortOrder)),
+        album_id: non_empty(tag.get_string(ItemKey::MusicBrainzReleaseId)),
+        // Only ID3 comments have languages. Vorbis comments have no descriptions, the key names the field, the same as for FLAC
+        comment: match tag.tag_type() {
+            TagType::VorbisComments if !options.comment_key.description.is_empty() => vorbis_field(filepath, &options.comment_key.description),
+            _ => non_empty(tag.get_items(ItemKey::Comment)
+                .find(|item| { item.description().eq_ignore_ascii_case(&options.comment_key.description) })
+                .and_then(|item| { item.value().text() })),
+        },
+    };
+
+    to_required_tags(required_tags, optional_tags, &options.required)
+}
+
+/// Splits artist or genre values further by separators used for multiple values in one
+fn split_artists<'a>(values: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    values.into_iter()
+        .flat_map(|value| { value.split(ARTIST_SEPARATORS) })
+        .map(|artist| { artist.trim() })
+        .filter(|artist| { !artist.is_empty() })
+        .map(String::from)
+        .collect()
+}
+
+/// Genre name of ID3v1 references, like "(17)" of ID3v2.3 or "17" of ID3v2.4
+fn resolve_genre(genre: &str) -> String {
+    if let Some(name) = genre.trim().parse().ok().and_then(|genre_id| { id3::v1::Tag { genre_id, ..Default::default() }.genre().map(String::from) }) {
+        return name;
+    }
+    let mut tag = id3::Tag::new();
+    tag.set_genre(genre);
+    tag.genre_parsed().map_or_else(|| { genre.to_string() }, |genre| { genre.into_owned() })
+}
+
+/// Sort name of the field `artist` was taken from. `fields` are (value, sort name) of album artist, artist and composer.
+/// Fields are matched by value, so that e.g. artist's sort name serves album artist of the same name without its own
+fn sort_name_of(artist: Option<&str>, fields: [(Option<&str>, Option<&str>); 3]) -> Option<String> {
+    let artist = artist?;
+    fields.into_iter()
+        .filter(|(value, _)| { *value == Some(artist) })
+        .find_map(|(_, sort_name)| { non_empty(sort_name) })
+}
+
+fn non_empty(value: Option<&str>) -> Option<String> {
+    value.map(str::trim).filter(|value| { !value.is_empty() }).map(String::from)
+}
+
+/// Compilation flag is set, or album artist is "Various Artists" in any case
+fn is_compilation(flag: Option<&str>, album_artist: Option<&str>) -> bool {
+    flag.is_some_and(|flag| { flag.trim() == "1" }) || album_artist.is_some_and(|artist| { artist.trim().eq_ignore_ascii_case(VARIOUS_ARTISTS) })
+}
+
+/// Value of TXXX frame, which description is case-insensitively equal to `description`
+fn extended_text(tag: &id3::Tag, description: &str) -> Option<String> {
+    tag.extended_texts()
+        .find(|text| { text.description.eq_ignore_ascii_case(description) })
+        .map(|text| { text.value.clone() })
+}
+
+/// Comment frame of `key`. Descriptions are compared case-insensitively, as taggers write them differently
+fn comment<'a>(tag: &'a id3::Tag, key: &CommentKey) -> Option<&'a str> {
+    tag.comments()
+        .find(|comment| {
+            comment.description.eq_ignore_ascii_case(&key.description)
+                && key.language.as_ref().is_none_or(|language| { comment.lang.eq_ignore_ascii_case(language) })
+        })
+        .map(|comment| { comment.text.as_str() })
+}
+
+/// Matches all fields of `missing_artist_error`
+fn is_artist_field(field: &str) -> bool {
+    ["album artist", "artist", "composer"].iter().any(|prefix| { field.starts_with(prefix) })
+}
+
+/// Error naming exactly the frame requested with `--artist-source`
+fn missing_artist_error(source: Option<ArtistSource>, album_artist_frame: &str, artist_frame: &str, composer_frame: &str) -> ManageError {
+    let field = match source {
+        None => String::from("album artist or artist"),
+        Some(ArtistSource::AlbumArtist) => format!("album artist ({})", album_artist_frame),
+        Some(ArtistSource::Artist) => format!("artist ({})", artist_frame),
+        Some(ArtistSource::Composer) => format!("composer ({})", composer_frame),
+    };
+    ManageError::missing_tag(field)
+}
+
+fn flac_error(err: metaflac::Error) -> ManageError {
+    match err.kind {
+        metaflac::ErrorKind::Io(io_err) => ManageError::io("Cannot read file", io_err),
+        _ => ManageError::ReadFailed { message: err.description.to_string() },
+    }
+}
+
+fn flac_write_error(err: metaflac::Error) -> ManageError {
+    match err.kind {
+        metaflac::ErrorKind::Io(io_err) => ManageError::io("Cannot write tags", io_err),
+        _ => flac_error(err),
+    }
+}
+
+fn id3_write_error(err: id3::Error) -> ManageError {
+    match err.kind {
+        id3::ErrorKind::Io(io_err) => ManageError::io("Cannot write tags", io_err),
+        _ => ManageError::from(err),
+    }
+}
+
+/// Reads sample rate, bit depth and channels of any supported format. Tags are skipped
+pub fn read_audio_properties(filepath: &Path) -> Result<AudioProperties, String> {
+    let read = || -> Result<lofty::file::TaggedFile, lofty::error::FileParseError> {
+        lofty::probe::Probe::open(filepath)?
+            .guess_file_type()?
+            .options(lofty::config::ParseOptions::new().read_tags(false))
+            .read()
+    };
+    let tagged_file = read().map_err(|err| { format!("Cannot read audio stream: {}", err) })?;
+    let properties = tagged_file.properties();
+
+    Ok(AudioProperties {
+        sample_rate: properties.sample_rate(),
+        bit_depth: properties.bit_depth(),
+        channels: properties.channels(),
+    })
+}
+
+/// Field of Ogg Vorbis or Opus comments. Lofty's generic tag leaves out fields it doesn't know, so the comments are read again
+fn vorbis_field(filepath: &Path, field: &str) -> Option<String> {
+    let file_type = lofty::probe::Probe::open(filepath).ok()?.guess_file_type().ok()?.file_type()?;
+    let mut reader = io::BufReader::new(std::fs::File::open(filepath).ok()?);
+    let options = lofty::config::ParseOptions::new().read_properties(false);
+    match file_type {
+        lofty::file::FileType::Vorbis => non_empty(lofty::ogg::VorbisFile::read_from(&mut reader, options).ok()?.vorbis_comments().get(field)),
+        lofty::file::FileType::Opus => non_empty(lofty::ogg::OpusFile::read_from(&mut reader, options).ok()?.vorbis_comments().get(field)),
+        _ => None,
+    }
+}
+
+/// Audio properties are not needed, and reading them fails on files with unusual stream layout
+fn read_lofty_file(filepath: &Path) -> Result<lofty::file::TaggedFile, lofty::error::FileParseError> {
+    // Content decides the format, extension may be wrong
+    lofty::probe::Probe::open(filepath)?
+        .guess_file_type()?
+        .options(lofty::config::ParseOptions::new().read_properties(false))
+        .read()
+}
+
+fn lofty_error(err: lofty::error::FileParseError) -> ManageError {
+    // IO errors are kept separate, so that they are reported the same way as for other formats
+    let io_err = std::error::Error::source(&err)
+        .and_then(|source| { source.downcast_ref::<io::Error>() })
+        .map(|io_err| { io::Error::new(io_err.kind(), io_err.to_string()) });
+
+    match io_err {
+        Some(io_err) => ManageError::io("Cannot read file", io_err),
+        None => ManageError::ReadFailed { message: err.to_string() },
+    }
+}
+
+/// `required_tags` are artist, album and title. Missing ones that are not `required` are left empty
+fn to_required_tags(required_tags: [Result<&str, ManageError>; 3], optional_tags: OptionalTags, required: &[Required]) -> Result<RequiredTags, Vec<ManageError>> {
+    let mut errors = Vec::new();
+    let mut values = Vec::new();
+    for (tag, field) in required_tags.into_iter().zip(Required::ALL) {
+        match tag {
+            Ok(value) => values.push(value.to_string()),
+            Err(_) if !required.contains(&field) => values.push(String::new()),
+            Err(err) => errors.push(err),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let [artist, album, title]: [String; 3] = values.try_into().expect("One value for each of artist, album and title");
+
+    let artists = if !optional_tags.artists.is_empty() {
+        optional_tags.artists
+    } else if artist.is_empty() {
+        Vec::new()
+    } else {
+        vec![artist.clone()]
+    };
+    Ok(RequiredTags {
+        album,
+        artists,
+        artist,
+        title,
+        year: optional_tags.year,
+        track: optional_tags.track,
+        total_tracks: optional_tags.total_tracks,
+        disc: optional_tags.disc,
+        total_discs: optional_tags.total_discs,
+        genre: optional_tags.genres.first().cloned(),
+        genres: optional_tags.genres,
+        track_gain: optional_tags.track_gain,
+        album_gain: optional_tags.album_gain,
+        track_artist: optional_tags.track_artist,
+        compilation: optional_tags.compilation,
+        artist_sort: optional_tags.artist_sort,
+        album_sort: optional_tags.album_sort,
+        full_artist: None,
+        full_album: None,
+        album_id: optional_tags.album_id,
+        comment: optional_tags.comment,
+        audio: AudioProperties::default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_options() -> ReadOptions {
+        ReadOptions {
+            artist_source: None,
+            singles_album: None,
+            missing_title: None,
+            allow_partial: false,
+            tag_version: TagVersion::PreferV2,
+            comment_key: CommentKey::default(),
+            required: Required::ALL.to_vec(),
+        }
+    }
+
+    #[test]
+    fn id3_year_falls_back_to_recording_date() {
+        let mut tag = id3::Tag::new();
+        tag.set_artist("Artist");
+        tag.set_album("Album");
+        tag.set_title("Title");
+        tag.set_date_recorded(id3::Timestamp { year: 1999, month: Some(4), day: Some(12), hour: None, minute: None, second: None });
+        assert!(tag.get("TYER").is_none());
+
+        let tags = id3_tags(&tag, &read_options()).ok().unwrap();
+        assert_eq!(tags.year, Some(1999));
+    }
+
+    #[test]
+    fn genre_values_are_resolved_like_id3_genres() {
+        let values = vec![
+            (String::from("artist"), String::from("Artist")),
+            (String::from("album"), String::from("Album")),
+            (String::from("title"), String::from("Title")),
+            (String::from("genre"), String::from("(17)")),
+        ];
+        let tags = tags_from_values(&values, &read_options()).ok().unwrap();
+        assert_eq!(tags.genre.as_deref(), Some("Rock"));
+
+        let mut tag = id3::Tag::new();
+        tag.set_artist("Artist");
+        tag.set_album("Album");
+        tag.set_title("Title");
+        tag.set_genre("(17)");
+        assert_eq!(id3_tags(&tag, &read_options()).ok().unwrap().genre, tags.genre);
+    }
+
+    #[test]
+    fn id3_year_frame_is_preferred() {
+        let mut tag = id3::Tag::new();
+        tag.set_artist("Artist");
+        tag.set_album("Album");
+        tag.set_title("Title");
+        tag.set_year(2001);
+        tag.set_date_recorded(id3::Timestamp { year: 1999, month: None, day: None, hour: None, minute: None, second: None });
+
+        let tags = id3_tags(&tag, &read_options()).ok().unwrap();
+        assert_eq!(tags.year, Some(2001));
+    }
+}
@@ -0,0 +1,282 @@
+use std::{fmt, str::FromStr};
+
+use crate::{error::ManageError, tags::{Required, RequiredTags}};
+
+/// Template reproducing the original `<Artist>/<Album>/<Title>` layout
+pub const DEFAULT_TEMPLATE: &str = "{artist}/{album}/{title}";
+/// Template of `--by-title-letter`, the letter directory is added on top of it
+pub const TITLE_LETTER_TEMPLATE: &str = "{title}";
+
+const SEGMENT_SEPARATOR: char = '/';
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Field {
+    Artist,
+    /// Artist before `--strip-featured` and `--ignore-articles`
+    FullArtist,
+    Album,
+    /// Album before `--clean-album`
+    FullAlbum,
+    Title,
+    Year,
+    Track,
+    Disc,
+    Genre,
+    Comment,
+    /// In kHz, e.g. "44.1"
+    SampleRate,
+    BitDepth,
+    Channels,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Field> {
+        match name {
+            "artist" => Some(Field::Artist),
+            "full_artist" => Some(Field::FullArtist),
+            "album" => Some(Field::Album),
+            "full_album" => Some(Field::FullAlbum),
+            "title" => Some(Field::Title),
+            "year" => Some(Field::Year),
+            "track" => Some(Field::Track),
+            "disc" => Some(Field::Disc),
+            "genre" => Some(Field::Genre),
+            "comment" => Some(Field::Comment),
+            "sample_rate" => Some(Field::SampleRate),
+            "bit_depth" => Some(Field::BitDepth),
+            "channels" => Some(Field::Channels),
+            _ => None,
+        }
+    }
+
+    /// Tag of `Required` the field is made of
+    fn required(&self) -> Option<Required> {
+        match self {
+            Field::Artist | Field::FullArtist => Some(Required::Artist),
+            Field::Album | Field::FullAlbum => Some(Required::Album),
+            Field::Title => Some(Required::Title),
+            _ => None,
+        }
+    }
+
+    fn is_numeric(&self) -> bool {
+        matches!(self, Field::Year | Field::Track | Field::Disc | Field::BitDepth | Field::Channels)
+    }
+
+    fn is_audio_property(&self) -> bool {
+        matches!(self, Field::SampleRate | Field::BitDepth | Field::Channels)
+    }
+
+    fn value(&self, tags: &RequiredTags, width: Option<usize>) -> Result<String, ManageError> {
+        let number = match self {
+            Field::Artist => return Ok(tags.artist.clone()),
+            Field::FullArtist => return Ok(tags.full_artist.clone().unwrap_or_else(|| { tags.artist.clone() })),
+            Field::Album => return Ok(tags.album.clone()),
+            Field::FullAlbum => return Ok(tags.full_album.clone().unwrap_or_else(|| { tags.album.clone() })),
+            Field::Title => return Ok(tags.title.clone()),
+            Field::Genre => return tags.genre.clone().ok_or(ManageError::missing_tag("genre")),
+            Field::Comment => return tags.comment.clone().ok_or(ManageError::missing_tag("comment")),
+            Field::SampleRate => return tags.audio.sample_rate_khz().ok_or(ManageError::missing_tag("sample rate")),
+            Field::BitDepth => tags.audio.bit_depth.map(i64::from).ok_or(ManageError::missing_tag("bit depth"))?,
+            Field::Channels => tags.audio.channels.map(i64::from).ok_or(ManageError::missing_tag("channels"))?,
+            Field::Year => tags.year.map(i64::from).ok_or(ManageError::missing_tag("year or recording date"))?,
+            Field::Track => tags.track.map(i64::from).ok_or(ManageError::missing_tag("track number"))?,
+            Field::Disc => tags.disc.map(i64::from).ok_or(ManageError::missing_tag("disc number"))?,
+        };
+
+        Ok(format!("{:0width$}", number, width = width.unwrap_or(0)))
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Part {
+    Literal(String),
+    /// `suffix` is set for conditional placeholders, which expand to nothing when the tag is missing
+    Placeholder { field: Field, width: Option<usize>, suffix: Option<String> },
+}
+
+/// Parsed destination path pattern.
+///
+/// Segments are separated by `/`, placeholders are written as `{name}` or `{name:0N}` for zero-padded numbers.
+/// Conditional `{name?text}` expands to the value followed by `text` if the tag is present and non-empty, and to nothing otherwise.
+/// Extension of the source file is appended to the last segment.
+#[derive(Clone, Debug)]
+pub struct Template {
+    segments: Vec<Vec<Part>>,
+}
+
+#[derive(Debug)]
+pub struct TemplateError(String);
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+impl Template {
+    /// Artist, album and title the template can't be expanded without. Conditional placeholders don't need their tags
+    pub fn required_tags(&self) -> Vec<Required> {
+        required_tags_of(&self.segments)
+    }
+
+    /// Same as `required_tags`, but only of directories, the file name of `--keep-filename` doesn't need any tags
+    pub fn directory_required_tags(&self) -> Vec<Required> {
+        required_tags_of(self.directory_segments())
+    }
+
+    /// Template has placeholders of audio properties, which have to be read from the stream
+    pub fn uses_audio_properties(&self) -> bool {
+        self.segments.iter().flatten().any(|part| { matches!(part, Part::Placeholder { field, .. } if field.is_audio_property()) })
+    }
+
+    /// Expands template into list of path segments (not normalized yet).
+    /// All missing tags are reported at once
+    pub fn expand(&self, tags: &RequiredTags) -> Result<Vec<String>, Vec<ManageError>> {
+        expand_segments(&self.segments, tags)
+    }
+
+    /// Expands all segments but the file name, for `--keep-filename`
+    pub fn expand_directories(&self, tags: &RequiredTags) -> Result<Vec<String>, Vec<ManageError>> {
+        expand_segments(self.directory_segments(), tags)
+    }
+
+    fn directory_segments(&self) -> &[Vec<Part>] {
+        &self.segments[..self.segments.len().saturating_sub(1)]
+    }
+}
+
+fn required_tags_of(segments: &[Vec<Part>]) -> Vec<Required> {
+    Required::ALL.into_iter()
+        .filter(|required| {
+            segments.iter().flatten().any(|part| {
+                matches!(part, Part::Placeholder { field, suffix: None, .. } if field.required() == Some(*required))
+            })
+        })
+        .collect()
+}
+
+/// All missing tags are reported at once
+fn expand_segments(template_segments: &[Vec<Part>], tags: &RequiredTags) -> Result<Vec<String>, Vec<ManageError>> {
+    let mut segments = Vec::new();
+    let mut errors = Vec::new();
+
+    for segment in template_segments {
+        let mut value = String::new();
+        for part in segment {
+            match part {
+                Part::Literal(text) => value.push_str(text),
+                Part::Placeholder { field, width, suffix: None } => match field.value(tags, *width) {
+                    Ok(field_value) => value.push_str(&field_value),
+                    Err(err) => errors.push(err),
+                },
+                Part::Placeholder { field, width, suffix: Some(suffix) } => {
+                    if let Some(field_value) = field.value(tags, *width).ok().filter(|field_value| { !field_value.trim().is_empty() }) {
+                        value.push_str(&field_value);
+                        value.push_str(suffix);
+                    }
+                },
+            }
+        }
+        segments.push(value);
+    }
+
+    if errors.is_empty() {
+        Ok(segments)
+    } else {
+        Err(errors)
+    }
+}
+
+impl FromStr for Template {
+    type Err = TemplateError;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let segments = template.split(SEGMENT_SEPARATOR)
+            .map(parse_segment)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Template { segments })
+    }
+}
+
+fn parse_segment(segment: &str) -> Result<Vec<Part>, TemplateError> {
+    if segment.is_empty() {
+        return Err(TemplateError(String::from("Template must not contain empty path segments")));
+    }
+
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut chars = segment.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => {
+                let mut placeholder = String::new();
+                let mut closed = false;
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        closed = true;
+                        break;
+                    }
+                    if c == '{' {
+                        return Err(TemplateError(format!("Unexpected '{{' inside of placeholder '{{{}'", placeholder)));
+                    }
+                    placeholder.push(c);
+                }
+
+                if !closed {
+                    return Err(TemplateError(format!("Unclosed placeholder '{{{}'", placeholder)));
+                }
+
+                if !literal.is_empty() {
+                    parts.push(Part::Literal(std::mem::take(&mut literal)));
+                }
+                parts.push(parse_placeholder(&placeholder)?);
+            },
+            '}' => return Err(TemplateError(format!("Unexpected '}}' in segment '{}'", segment))),
+            _ => literal.push(c),
+        }
+    }
+
+    if !literal.is_empty() {
+        parts.push(Part::Literal(literal));
+    }
+
+    Ok(parts)
+}
+
+fn parse_placeholder(placeholder: &str) -> Result<Part, TemplateError> {
+    let (placeholder, suffix) = match placeholder.split_once('?') {
+        Some((placeholder, suffix)) => (placeholder, Some(suffix.to_string())),
+        None => (placeholder, None),
+    };
+    if suffix.as_deref().is_some_and(|suffix| { suffix.contains('?') }) {
+        return Err(TemplateError(format!("Conditional placeholder '{{{}?...}}' must contain only one '?'", placeholder)));
+    }
+
+    let (name, spec) = match placeholder.split_once(':') {
+        Some((name, spec)) => (name, Some(spec)),
+        None => (placeholder, None),
+    };
+
+    let field = Field::from_name(name)
+        .ok_or_else(|| TemplateError(format!("Unknown placeholder '{{{}}}'", placeholder)))?;
+
+    let width = match spec {
+        None => None,
+        Some(spec) => {
+            if !field.is_numeric() {
+                return Err(TemplateError(format!("Placeholder '{{{}}}' does not support padding", name)));
+            }
+            let width = spec.strip_prefix('0')
+                .and_then(|width| width.parse::<usize>().ok())
+                .ok_or_else(|| TemplateError(format!("Invalid format '{}' in placeholder '{{{}}}', expected e.g. ':02'", spec, placeholder)))?;
+            Some(width)
+        }
+    };
+
+    Ok(Part::Placeholder { field, width, suffix })
+}
@@ -0,0 +1,86 @@
+use std::{fs, path::Path};
+
+use colored::Colorize;
+
+use crate::{checksum, manifest::{self, Action, RecordedEntry}, report::Summary};
+
+enum Reverted {
+    Removed,
+    MovedBack,
+}
+
+/// Reverts entries of the manifest in reverse order: written destinations are removed, moved files are put back.
+/// Destinations changed since the run are left untouched
+pub fn undo(manifest_path: &Path, target_directory: Option<&Path>) -> Result<Summary, String> {
+    let entries = manifest::read(manifest_path).map_err(|err| { err.to_string() })?;
+    let entries: Vec<&RecordedEntry> = entries.iter()
+        .rev()
+        .filter(|entry| { entry.status == manifest::STATUS_OK && is_reversible(entry.action) })
+        .collect();
+
+    let longest_filename_len = entries.iter()
+        .map(|entry| { filename(&entry.source).chars().count() })
+        .max()
+        .unwrap_or(1);
+
+    let mut summary = Summary::default();
+    for entry in entries {
+        let name = filename(&entry.source);
+        let dots = ".".repeat(longest_filename_len - name.chars().count() + 10);
+        match revert(entry, target_directory) {
+            Ok(Reverted::Removed) => {
+                summary.ok += 1;
+                println!("{}{}{}", name, dots, "Removed".green().bold());
+            },
+            Ok(Reverted::MovedBack) => {
+                summary.ok += 1;
+                println!("{}{}{}", name, dots, "Moved back".green().bold());
+            },
+            Err(err) => {
+                summary.errors += 1;
+                println!("{}{}{}", name, dots, err.red());
+            },
+        }
+    }
+
+    Ok(summary)
+}
+
+fn is_reversible(action: Action) -> bool {
+    matches!(action, Action::Copied | Action::Moved | Action::Hardlinked | Action::Symlinked)
+}
+
+fn revert(entry: &RecordedEntry, target_directory: Option<&Path>) -> Result<Reverted, String> {
+    let destination = entry.destination.as_deref().ok_or("No destination recorded")?;
+    if fs::symlink_metadata(destination).is_err() {
+        return Err(String::from("Destination no longer exists"));
+    }
+
+    let expected_checksum = entry.checksum.as_deref().ok_or("No checksum recorded, refusing to touch destination")?;
+    let checksum = checksum::sha256_file(destination).map_err(|err| { format!("Cannot read destination: {}", err) })?;
+    if checksum != expected_checksum {
+        return Err(String::from("Destination was changed after the run, refusing to touch it"));
+    }
+
+    let reverted = if entry.action == Action::Moved {
+        if fs::symlink_metadata(&entry.source).is_ok() {
+            return Err(String::from("Original path is taken by another file"));
+        }
+        crate::move_file_to(destination, &entry.source, true).map_err(|err| { crate::format_error(&err) })?;
+        Reverted::MovedBack
+    } else {
+        fs::remove_file(destination).map_err(|err| { format!("Cannot remove destination: {}", err) })?;
+        Reverted::Removed
+    };
+
+    // Without a known root the climb wouldn't stop at the library, and would remove directories above it
+    let root = entry.root.as_deref().or(target_directory).filter(|root| { destination.starts_with(root) });
+    if let (Some(album_dir), Some(root)) = (destination.parent(), root) {
+        crate::remove_empty_dirs(album_dir, &[root]);
+    }
+    Ok(reverted)
+}
+
+fn filename(path: &Path) -> &str {
+    path.file_name().and_then(|fname| { fname.to_str() }).unwrap_or("<N/A>")
+}
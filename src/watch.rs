@@ -0,0 +1,83 @@
+use std::{collections::HashMap, fs, ops::ControlFlow, path::{Path, PathBuf}, sync::{atomic::{AtomicBool, Ordering}, mpsc, Arc}, time::{Duration, Instant}};
+
+use globset::GlobSet;
+use notify::{EventKind, RecursiveMode, Watcher};
+
+use crate::input;
+
+/// How often pending files are checked and Ctrl-C is noticed
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Size of a new file must stay the same for this long before it's handled
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+/// New file, which is possibly still being written
+struct Pending {
+    size: u64,
+    changed_at: Instant,
+}
+
+/// Calls `on_file` for every audio file, that is in `directory` at start or appears in it later, once it's completely written.
+/// Runs until Ctrl-C is pressed or `on_file` breaks
+pub fn watch(directory: &Path, recursive: bool, extensions: &[String], mut on_file: impl FnMut(&Path) -> ControlFlow<()>) -> Result<(), String> {
+    let stopped = Arc::new(AtomicBool::new(false));
+    let handler_stopped = Arc::clone(&stopped);
+    ctrlc::set_handler(move || { handler_stopped.store(true, Ordering::Relaxed) })
+        .map_err(|err| { format!("Cannot handle Ctrl-C: {}", err) })?;
+
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).map_err(|err| { err.to_string() })?;
+    let mode = if recursive { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+    watcher.watch(directory, mode).map_err(|err| { err.to_string() })?;
+
+    // Collected after subscribing, so that no file is missed in between. Files reported by both are the same pending entry
+    let mut pending: HashMap<PathBuf, Pending> = input::collect_files(&[directory.to_path_buf()], recursive, extensions, &GlobSet::empty())
+        .into_iter()
+        .map(|path| { (path, Pending { size: 0, changed_at: Instant::now() }) })
+        .collect();
+    while !stopped.load(Ordering::Relaxed) {
+        match receiver.recv_timeout(POLL_INTERVAL) {
+            Ok(Ok(event)) => {
+                if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                    for path in event.paths.into_iter().filter(|path| { input::is_audio_file(path, extensions) }) {
+                        // Every event restarts waiting, so that rapid writes are handled once
+                        pending.insert(path, Pending { size: 0, changed_at: Instant::now() });
+                    }
+                }
+            },
+            Ok(Err(err)) => crate::print_warning(&format!("Error while watching '{}': {}", directory.display(), err)),
+            Err(mpsc::RecvTimeoutError::Timeout) => {},
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Err(String::from("Watching stopped unexpectedly")),
+        }
+
+        for path in take_settled(&mut pending) {
+            if on_file(&path).is_break() {
+                return Ok(());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes files, which size hasn't changed for `SETTLE_TIME`, from `pending`. Files that are gone are dropped
+fn take_settled(pending: &mut HashMap<PathBuf, Pending>) -> Vec<PathBuf> {
+    let mut settled = Vec::new();
+    pending.retain(|path, file| {
+        let Ok(size) = fs::metadata(path).map(|metadata| { metadata.len() }) else {
+            return false;
+        };
+        if size != file.size {
+            file.size = size;
+            file.changed_at = Instant::now();
+            return true;
+        }
+        if file.changed_at.elapsed() < SETTLE_TIME {
+            return true;
+        }
+        settled.push(path.clone());
+        false
+    });
+    // Files of one album come in order more often than not
+    settled.sort();
+    settled
+}
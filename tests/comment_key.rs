@@ -0,0 +1,30 @@
+use std::path::Path;
+
+use music_shelf_manager::{tags, Config};
+
+fn comment(name: &str, key: Option<&str>) -> Option<String> {
+    let mut config = Config::new("/music");
+    if let Some(key) = key {
+        config.comment_key = key.parse().expect("Key is valid");
+    }
+    let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name);
+    tags::read_tags(&source, &config.read_options()).expect("Fixture has tags").comment
+}
+
+#[test]
+fn comment_key_names_flac_field() {
+    assert_eq!(comment("comment.flac", Some("MOOD")).as_deref(), Some("Calm"));
+    assert_eq!(comment("comment.flac", None).as_deref(), Some("Plain"));
+}
+
+#[test]
+fn comment_key_names_opus_field() {
+    assert_eq!(comment("comment.opus", Some("MOOD")).as_deref(), Some("Calm"));
+    assert_eq!(comment("comment.opus", None).as_deref(), Some("Plain"));
+}
+
+#[test]
+fn comment_key_names_ogg_field() {
+    assert_eq!(comment("comment.ogg", Some("mood")).as_deref(), Some("Calm"));
+    assert_eq!(comment("comment.ogg", None).as_deref(), Some("Plain"));
+}
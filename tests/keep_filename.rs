@@ -0,0 +1,37 @@
+use std::{path::Path, process::Command};
+
+use music_shelf_manager::{generate_target_path, tags::{self, Required}, Config};
+
+fn fixture(name: &str) -> std::path::PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+}
+
+#[test]
+fn kept_filename_does_not_need_title() {
+    let source = fixture("no-title.m4a");
+    let mut config = Config::new("/music");
+    config.keep_filename = true;
+    config.required = vec![Required::Artist, Required::Album];
+    let tags = tags::read_tags(&source, &config.read_options()).expect("Fixture has artist and album");
+
+    let target = generate_target_path(&source, Some("m4a"), &tags, &config).expect("Title isn't expanded");
+    assert_eq!(target, Path::new("/music/Album Artist/Album/no-title.m4a"));
+}
+
+#[test]
+fn require_without_title_is_accepted_with_kept_filename() {
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_music-shelf-manager"))
+            .args(["--dry-run", "--require", "artist,album"])
+            .args(args)
+            .arg(fixture("no-title.m4a"))
+            .output()
+            .expect("Binary runs")
+    };
+
+    let kept = run(&["--keep-filename"]);
+    assert!(kept.status.success(), "{}", String::from_utf8_lossy(&kept.stderr));
+    assert!(String::from_utf8_lossy(&kept.stdout).contains("no-title.m4a"));
+
+    assert_eq!(run(&[]).status.code(), Some(2));
+}
@@ -0,0 +1,28 @@
+use std::path::Path;
+
+use music_shelf_manager::{generate_target_path, tags, Config, ManageError};
+
+fn target_of(fixture: &str) -> std::path::PathBuf {
+    let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(fixture);
+    let config = Config::new("/music");
+    let tags = tags::read_tags(&source, &config.read_options()).expect("Fixture has all required tags");
+    generate_target_path(&source, Some("m4a"), &tags, &config).expect("Target path is valid")
+}
+
+#[test]
+fn m4a_target_uses_album_artist() {
+    assert_eq!(target_of("sample.m4a"), Path::new("/music/Album Artist/Album/Title.m4a"));
+}
+
+#[test]
+fn m4a_target_falls_back_to_artist() {
+    assert_eq!(target_of("no-album-artist.m4a"), Path::new("/music/Track Artist/Album/Title.m4a"));
+}
+
+#[test]
+fn raw_aac_is_reported_as_unsupported() {
+    let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/adts.m4a");
+    assert_eq!(tags::detect_format(&source), Some("aac"));
+    let errors = tags::read_tags(&source, &Config::new("/music").read_options()).expect_err("ADTS tags can't be read");
+    assert!(matches!(errors.as_slice(), [ManageError::Unsupported { .. }]));
+}
@@ -0,0 +1,31 @@
+use std::{env, fs, path::Path};
+
+use music_shelf_manager::{handle_file, ConflictAction, Config, RunState};
+
+#[test]
+fn renamed_target_fits_max_path_length() {
+    let root = env::temp_dir().join(format!("max-path-length-test-{}", std::process::id()));
+    fs::create_dir_all(&root).unwrap();
+    let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/sample.m4a");
+    // "<root>/Album Artist/Album/Title.m4a" fits, but not with " (1)" added
+    let max_length = root.to_string_lossy().chars().count() + "/Album Artist/Album/Title.m4a".len() + 2;
+
+    let mut config = Config::new(&root);
+    config.max_path_length = Some(max_length);
+    config.on_conflict = ConflictAction::Rename;
+    let state = RunState::default();
+    let targets: Vec<_> = (0..3)
+        .map(|_| {
+            let outcome = handle_file(&source, &config, &state).pop().unwrap().ok().unwrap();
+            outcome.target().to_path_buf()
+        })
+        .collect();
+    fs::remove_dir_all(&root).unwrap();
+
+    for target in &targets {
+        assert!(target.to_string_lossy().chars().count() <= max_length, "{} is too long", target.display());
+    }
+    assert_ne!(targets[0], targets[1]);
+    assert_ne!(targets[1], targets[2]);
+    assert!(targets[1].to_string_lossy().ends_with(" (1).m4a"));
+}
@@ -0,0 +1,26 @@
+use std::{env, fs, path::Path};
+
+use music_shelf_manager::{handle_file, rollback, Config, RunState};
+
+#[test]
+fn rollback_keeps_directories_that_existed_before() {
+    let root = env::temp_dir().join(format!("rollback-test-{}", std::process::id()));
+    let artist_dir = root.join("Album Artist");
+    fs::create_dir_all(&artist_dir).unwrap();
+
+    let mut config = Config::new(&root);
+    config.rollback_on_error = true;
+    let state = RunState::default();
+    let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/sample.m4a");
+    assert!(handle_file(&source, &config, &state).pop().unwrap().is_ok());
+    assert!(artist_dir.join("Album/Title.m4a").is_file());
+
+    let rolled_back = rollback(&state);
+    let album_dir_exists = artist_dir.join("Album").exists();
+    let artist_dir_exists = artist_dir.is_dir();
+    fs::remove_dir_all(&root).unwrap();
+
+    assert_eq!(rolled_back, (1, 1));
+    assert!(!album_dir_exists);
+    assert!(artist_dir_exists);
+}
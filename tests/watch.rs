@@ -0,0 +1,21 @@
+use std::{env, fs, ops::ControlFlow, path::Path};
+
+use music_shelf_manager::watch;
+
+#[test]
+fn files_present_at_start_are_handled() {
+    let inbox = env::temp_dir().join(format!("watch-test-{}", std::process::id()));
+    fs::create_dir_all(&inbox).unwrap();
+    let file = inbox.join("sample.m4a");
+    fs::copy(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/sample.m4a"), &file).unwrap();
+
+    let mut handled = Vec::new();
+    let result = watch::watch(&inbox, false, &[String::from("m4a")], |path| {
+        handled.push(path.to_path_buf());
+        ControlFlow::Break(())
+    });
+    fs::remove_dir_all(&inbox).unwrap();
+
+    assert_eq!(result, Ok(()));
+    assert_eq!(handled, vec![file]);
+}
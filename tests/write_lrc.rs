@@ -0,0 +1,36 @@
+use std::{env, fs, path::Path};
+
+use music_shelf_manager::{handle_file, Config, RunState};
+
+#[test]
+fn existing_lyrics_file_is_kept() {
+    let root = env::temp_dir().join(format!("write-lrc-test-{}", std::process::id()));
+    let album_dir = root.join("Album Artist/Album");
+    fs::create_dir_all(&album_dir).unwrap();
+    let lrc_path = album_dir.join("Title.lrc");
+    fs::write(&lrc_path, "Edited lyrics").unwrap();
+
+    let mut config = Config::new(&root);
+    config.write_lrc = true;
+    let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/lyrics.m4a");
+    let outcome = handle_file(&source, &config, &RunState::default()).pop().unwrap();
+    let lyrics = fs::read_to_string(&lrc_path).unwrap();
+    fs::remove_dir_all(&root).unwrap();
+
+    assert!(outcome.is_ok());
+    assert_eq!(lyrics, "Edited lyrics");
+}
+
+#[test]
+fn missing_lyrics_file_is_written() {
+    let root = env::temp_dir().join(format!("write-lrc-new-test-{}", std::process::id()));
+    let mut config = Config::new(&root);
+    config.write_lrc = true;
+    let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/lyrics.m4a");
+    let outcome = handle_file(&source, &config, &RunState::default()).pop().unwrap();
+    let lyrics = fs::read_to_string(root.join("Album Artist/Album/Title.lrc")).unwrap();
+    fs::remove_dir_all(&root).unwrap();
+
+    assert!(outcome.is_ok());
+    assert_eq!(lyrics, "New lyrics");
+}